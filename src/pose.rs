@@ -19,6 +19,12 @@ impl From<Isometry2<f32>> for Pose {
     }
 }
 
+impl From<Pose> for Isometry2<f32> {
+    fn from(src: Pose) -> Self {
+        Isometry2::new([src.x, src.y].into(), src.theta)
+    }
+}
+
 const METER_LEN: f32 = 1.0 / CONFIG.len_meter as f32;
 const RAD_DIR: f32 = 2.0 * PI / CONFIG.dir_round as f32;
 
@@ -19,13 +19,21 @@ use std::{
     time::{Duration, Instant},
 };
 
+mod calibration;
 mod chassis;
 #[macro_use]
 mod filter;
 mod drive_blocking;
+mod gnss_model;
 mod joystick;
 mod lidar;
+mod mapping;
+mod persist;
+mod profile;
+mod quality;
 mod rtk;
+mod scan_matching;
+mod stuck;
 
 #[cfg(feature = "display")]
 mod display;
@@ -33,12 +41,32 @@ mod display;
 #[cfg(feature = "display")]
 use display::*;
 
+use calibration::Calibration;
 use chassis::Chassis;
-use drive_blocking::DriveBlocking;
+use drive_blocking::{DriveBlocking, ARTIFICIAL, AUTOMATIC, JOYSTICK};
+use filter::{reweight, statistics};
+use gnss_model::KidnapDetector;
+use joystick::Joystick;
 use lidar::Lidar;
+use mapping::{LikelihoodField, OccupancyGrid};
+use persist::{SavedState, STATE_FILE};
+use quality::{GateAction, GateMonitor, QualityMonitor};
+use scan_matching::{OdometryHistory, ScanMatcher};
 
+pub use calibration::{CalibrationError, CalibrationResult};
+pub use drive_blocking::{ControlSource, ControlSourceError};
+pub use filter::AdaptiveSampling;
+pub use gnss_model::{GgaQuality, GnssModel, GnssRejection};
+pub use joystick::{GamepadAction, GamepadButton, JoystickProfile, ResponseCurve};
 pub use pm1_sdk::PM1Status;
-pub use rtk::reauth;
+pub use profile::MotionProfile;
+pub use quality::{
+    Convergence, DegradeReason, LocalizationQuality, MeasurementSource, QualityGate,
+    TrackingRestriction,
+};
+pub use rtk::{caster_stats, reauth, AuthFailure, CasterStats, CredentialError, RtkAuth};
+pub use scan_matching::ScanMatch;
+pub use stuck::{MotionFault, StuckConfig};
 pub type Trajectory = Box<TrajectoryPredictor<Pm1Predictor>>;
 
 #[derive(Clone)]
@@ -47,10 +75,26 @@ pub struct Robot {
     chassis: Chassis,
     lidar: Lidar,
     event: Sender<Event>,
+    localization: Sender<Localization>,
 
     drive_blocking: DriveBlocking,
     tracking_speed: Arc<AtomicU32>,
     task: Arc<Mutex<Task>>,
+    shared: Arc<Mutex<Option<SharedControl>>>,
+    pose: Arc<Mutex<Option<Isometry2<f32>>>>,
+    dead_reckoning: Arc<Mutex<Option<Isometry2<f32>>>>,
+    odometry: Arc<Mutex<OdometryHistory>>,
+    rtk_status: Arc<Mutex<GpggaStatus>>,
+    gnss_model: Arc<Mutex<GnssModel>>,
+    sampling: Arc<Mutex<AdaptiveSampling>>,
+    quality: Arc<Mutex<QualityMonitor>>,
+    gate: Arc<Mutex<GateMonitor>>,
+    mapping: Arc<Mutex<Option<OccupancyGrid>>>,
+    map: Arc<Mutex<Option<Arc<LikelihoodField>>>>,
+    stuck: Arc<Mutex<stuck::Monitor>>,
+    bindings: Arc<Mutex<Vec<(GamepadButton, GamepadAction)>>>,
+    joystick_profile: Arc<Mutex<JoystickProfile>>,
+    speed_presets: Arc<Mutex<(Vec<f32>, usize)>>,
 
     #[cfg(feature = "display")]
     painter: Painter,
@@ -64,13 +108,64 @@ pub enum Event {
     PoseUpdated(Pose),
     LidarFrameEncoded(Vec<u8>),
     CollisionDetected(f32),
+    CollisionAvoiding(Avoidance),
+    EmergencyStopTriggered,
+    EmergencyStopReset,
+    Stuck,
+    Slipping,
+    LidarDegraded(usize, bool),
+    ControlSourceChanged(Option<String>),
+    GamepadActionTriggered(GamepadAction),
+    WaypointMarked(Pose),
+    ScanMatched(ScanMatch),
+    Relocalized(Pose),
+    GnssRejected(GnssRejection),
+    LocalizationQuality(LocalizationQuality),
+    ConvergenceChanged(Convergence),
+    TrackingRestricted(TrackingRestriction),
+    CalibrationFinished(Result<CalibrationResult, CalibrationError>),
+    RtkAuthChanged(RtkAuth),
+    CasterFailed(async_std::io::Error),
 }
 
-struct CollisionInfo {
+/// 预测的碰撞
+#[derive(Clone)]
+pub struct CollisionInfo {
+    /// 到达碰撞位置的时间
     pub time: Duration,
+    /// 碰撞时的位姿
     pub pose: Odometry,
     pub risk: f32,
+    /// 障碍物斥力
     pub force: Vector2<f32>,
+    /// 触发碰撞的障碍物点，机器人坐标系
+    pub obstacle: Point2<f32>,
+    /// 看到障碍物的雷达序号
+    pub lidar: usize,
+}
+
+/// 避障决策
+#[derive(Clone)]
+pub struct Avoidance {
+    pub collision: CollisionInfo,
+    /// 原始控制目标
+    pub original: Physical,
+    /// 避障修正后的控制目标
+    pub modified: Physical,
+}
+
+/// 共享控制：循迹控制转向，手柄调整速度和横向偏移
+struct SharedControl {
+    max_offset: f32,
+    input: Option<(Instant, Physical)>,
+}
+
+/// 发往定位任务的指令
+enum Localization {
+    /// 在给定位姿附近重新撒布粒子
+    SetPose(Isometry2<f32>, f32),
+    /// 标定路线已走完，拟合并应用结果
+    Calibrated(Calibration),
 }
 
 enum Task {
@@ -78,13 +173,36 @@ enum Task {
     WaitingPose,
     Record(RecordFile),
     Track(Path, TrackContext),
+    Calibrate(Calibration),
 }
 
 const ACTIVE_COLLISION_AVOIDING: f32 = 2.5; // 主动避障强度
+const EMERGENCY_STOP_FILE: &str = "estop"; // 急停锁定标记文件
+const DEGRADED_SPEED: f32 = 0.2; // 雷达失明时的限速
+const WAYPOINTS_FILE: &str = "waypoints"; // 手动标记的航点
+const SCAN_MATCHING_PERIOD: Duration = Duration::from_millis(200); // 点云匹配周期
+const SCAN_MATCHING_SIGMA: f32 = 0.05; // 点云匹配观测标准差
+const MAPPING_PERIOD: Duration = Duration::from_millis(200); // 建图周期
+const MAP_LOCALIZATION_PERIOD: Duration = Duration::from_millis(200); // 地图定位周期
+const MAP_LOCALIZATION_POINTS: usize = 60; // 地图定位每次使用的点数
+const MAP_LOCALIZATION_DISTANCE: f32 = 0.2; // 地图定位前至少移动的距离（m）
+const MAP_LOCALIZATION_ANGLE: f32 = 0.1; // 地图定位前至少转动的角度（rad）
+const SHARED_INPUT_TIMEOUT: Duration = Duration::from_millis(300); // 共享控制手柄输入有效期
+const QUALITY_PERIOD: Duration = Duration::from_secs(1); // 定位质量报告周期
+const KIDNAP_SIGMA: f32 = 0.5; // 被挪动后重新撒布粒子的标准差
+const PERSIST_PERIOD: Duration = Duration::from_secs(10); // 定位状态保存周期
+const WARM_START_SIGMA: f32 = 0.1; // 热启动的最小位置标准差（m），容纳停机期间的移动
+const WARM_START_HEADING: f32 = 0.1; // 热启动的最小方向标准差（rad）
 
 impl Robot {
     pub async fn spawn(mut context_dir: PathBuf, rtk: bool) -> (Self, Receiver<Event>) {
         let device_code = AtomicDeviceCode::default();
+        // 没有 RTK 时仅靠轮速计推算位姿
+        let dead_reckoning = if rtk {
+            None
+        } else {
+            Some(Isometry2::identity())
+        };
         let rtk = if rtk {
             rtk::supervisor(context_dir.clone())
         } else {
@@ -93,23 +211,98 @@ impl Robot {
         let (chassis, from_chassis) = Chassis::supervisor();
         let (lidar, from_lidar) = Lidar::supervisor();
         let (event, to_extern) = unbounded();
+        let (localization, from_robot) = unbounded();
 
+        // 恢复上次运行的急停锁定
+        if context_dir.join(EMERGENCY_STOP_FILE).exists().await {
+            chassis.set_emergency_stop(true).await;
+            let _ = event.send(Event::EmergencyStopTriggered).await;
+        }
+
+        let drive_blocking = DriveBlocking::new(event.clone());
         context_dir.push("path");
         let robot = Self {
             context_dir,
             chassis,
             lidar,
             event,
+            localization,
 
-            drive_blocking: DriveBlocking::new(),
+            drive_blocking,
             tracking_speed: Arc::new(AtomicU32::new(0f32.to_bits())),
             task: Arc::new(Mutex::new(Task::Idle)),
+            shared: Default::default(),
+            pose: Default::default(),
+            dead_reckoning: Arc::new(Mutex::new(dead_reckoning)),
+            odometry: Default::default(),
+            rtk_status: Arc::new(Mutex::new(GpggaStatus::无效解)),
+            gnss_model: Default::default(),
+            sampling: Default::default(),
+            quality: Default::default(),
+            gate: Default::default(),
+            mapping: Default::default(),
+            map: Default::default(),
+            stuck: Arc::new(Mutex::new(stuck::Monitor::new(Default::default()))),
+            bindings: Arc::new(Mutex::new(joystick::default_bindings())),
+            joystick_profile: Default::default(),
+            speed_presets: Arc::new(Mutex::new((vec![0.2, 0.4, 0.6], 0))),
             #[cfg(feature = "display")]
             painter: Painter::new().await,
         };
 
         let filter = particle_filter!();
         let time_origin = Instant::now();
+        // 从上次保存的状态热启动
+        let saved = SavedState::load(robot.context_file(STATE_FILE).as_path()).await;
+        if let Some(ref model) = saved.model {
+            filter.lock().await.parameters.default_model = model.clone();
+            robot.chassis.update_model(model.clone()).await;
+        }
+        if let Some(offset) = saved.rudder_offset {
+            robot.chassis.set_rudder_offset(offset);
+        }
+        // 航位推算和滤波器的位姿不在同一坐标系，各自恢复
+        let mut warm_start = false;
+        if robot.dead_reckoning.lock().await.is_some() {
+            if let Some(pose) = saved.odometry {
+                robot.set_dead_reckoning_pose(pose.into()).await;
+            }
+        } else if let Some((pose, sigma, heading)) = saved.pose {
+            let sigma = f32::max(sigma, WARM_START_SIGMA);
+            let heading = f32::max(heading, WARM_START_HEADING);
+            let mut filter = filter.lock().await;
+            let beacon = pose * filter.parameters.beacon_on_robot;
+            filter.measure(Instant::now() - time_origin, beacon, sigma);
+            scatter!(filter, pose, sigma, heading);
+            warm_start = true;
+        }
+        {
+            let robot = robot.clone();
+            let filter = filter.clone();
+            task::spawn(async move {
+                let path = robot.context_file(STATE_FILE);
+                let mut state = saved;
+                loop {
+                    task::sleep(PERSIST_PERIOD).await;
+                    let filter = filter.lock().await;
+                    state.model = Some(filter.parameters.default_model.clone());
+                    state.rudder_offset = Some(robot.chassis.rudder_offset());
+                    let pose = filter.get();
+                    std::mem::drop(filter);
+                    // 只保存收敛的位姿，否则保留上次的
+                    if let Some(pose) = *robot.dead_reckoning.lock().await {
+                        state.odometry = Some(pose);
+                    } else if let (Some(pose), Some(q)) =
+                        (pose, robot.quality.lock().await.quality())
+                    {
+                        if q.is_trustworthy() {
+                            state.pose = Some((pose, q.spread(), q.heading_variance.sqrt()));
+                        }
+                    }
+                    let _ = state.save(path.as_path()).await;
+                }
+            });
+        }
         {
             let filter = filter.clone();
             let robot = robot.clone();
@@ -117,6 +310,7 @@ impl Robot {
             task::spawn(async move {
                 let local_ref = LocalReference::from(super::LOCAL_ORIGIN);
                 let mut status = GpggaStatus::无效解;
+                let mut kidnap = KidnapDetector::default();
                 while let Ok(e) = rtk.recv().await {
                     use rtk::Event::*;
                     match e {
@@ -140,7 +334,13 @@ impl Robot {
                                 send_async!(Event::ConnectionModified(code) => robot.event).await;
                             }
                         }
-                        Gpgga(t, gpgga) => {
+                        Auth(auth) => {
+                            send_async!(Event::RtkAuthChanged(auth) => robot.event).await;
+                        }
+                        CasterFailed(e) => {
+                            send_async!(Event::CasterFailed(e) => robot.event).await;
+                        }
+                        Gpgga(t, gpgga, quality) => {
                             let enu = local_ref.wgs84_to_enu(WGS84 {
                                 latitude: gpgga.latitude,
                                 longitude: gpgga.longitude,
@@ -150,41 +350,89 @@ impl Robot {
                             robot.painter.paint_gps(gpgga.status, enu).await;
                             if status != gpgga.status {
                                 status = gpgga.status;
+                                *robot.rtk_status.lock().await = status;
                                 send_async!(Event::RtkStatusUpdated(status) => robot.event).await;
                             }
-                            if let Some(sigma) = match gpgga.status {
-                                GpggaStatus::单点解 => Some(0.32),
-                                GpggaStatus::伪距差分 => Some(0.16),
-                                GpggaStatus::浮点解 => Some(0.08),
-                                GpggaStatus::固定解 => Some(0.04),
-                                GpggaStatus::无效解
-                                | GpggaStatus::PPS
-                                | GpggaStatus::航位推算
-                                | GpggaStatus::用户输入
-                                | GpggaStatus::PPP => None,
-                            } {
+                            if let Task::Calibrate(c) = &mut *robot.task.lock().await {
+                                let fixed = gpgga.status == GpggaStatus::固定解;
+                                let p = point(enu.e as f32, enu.n as f32);
+                                c.update_gnss(t, if fixed { Some(p) } else { None });
+                            }
+                            let model = *robot.gnss_model.lock().await;
+                            let sigma = match model.sigma(gpgga.status, &quality) {
+                                Ok(sigma) => sigma,
+                                Err(rejection) => {
+                                    send_async!(Event::GnssRejected(rejection) => robot.event)
+                                        .await;
+                                    None
+                                }
+                            };
+                            if let Some(sigma) = sigma {
+                                let measurement = point(enu.e as f32, enu.n as f32);
                                 let mut filter = filter.lock().await;
-                                filter.measure(
-                                    t - time_origin,
-                                    point(enu.e as f32, enu.n as f32),
-                                    sigma,
+                                // 与粒子群不一致的观测视为多路径跳变
+                                let gate = filter.get().zip(statistics(filter.particles())).map(
+                                    |(pose, stats)| {
+                                        let predicted = pose * filter.parameters.beacon_on_robot;
+                                        let innovation = (measurement - predicted).norm();
+                                        model.gate(innovation, sigma, stats.spread())
+                                    },
                                 );
+                                if let Some(Err(rejection)) = gate {
+                                    // 热启动的位姿未被 GNSS 证实前，不一致时立即以观测为准
+                                    if !warm_start && !kidnap.rejected(t) {
+                                        std::mem::drop(filter);
+                                        send_async!(Event::GnssRejected(rejection) => robot.event)
+                                            .await;
+                                        continue;
+                                    }
+                                    // 持续不一致，以观测为准重新撒布粒子，保留当前方向
+                                    let rotation = filter.get().unwrap().rotation;
+                                    let beacon = filter.parameters.beacon_on_robot;
+                                    let pose = Isometry2::new(
+                                        measurement - rotation * beacon,
+                                        rotation.angle(),
+                                    );
+                                    scatter!(filter, pose, KIDNAP_SIGMA);
+                                    std::mem::drop(filter);
+                                    warm_start = false;
+                                    robot.reset_convergence(MeasurementSource::Gnss, t).await;
+                                    send_async!(Event::Relocalized(pose.into()) => robot.event)
+                                        .await;
+                                    continue;
+                                }
+                                kidnap.accepted();
+                                warm_start = false;
+                                filter.measure(t - time_origin, measurement, sigma);
                                 let (wheel, weight) = filter
                                     .fold_models(0.0, |wheel, model, weight| {
                                         wheel + model.wheel * weight
                                     });
                                 if weight.is_normal() {
-                                    let wheel = wheel / weight;
-                                    filter.parameters.default_model.wheel = wheel;
-                                    println!("wheel = {}", wheel);
+                                    filter.parameters.default_model.wheel = wheel / weight;
                                 }
+                                robot
+                                    .quality
+                                    .lock()
+                                    .await
+                                    .measured(MeasurementSource::Gnss, t);
                                 if let Some(pose) = filter.get() {
                                     #[cfg(feature = "display")]
                                     robot.painter.paint_filter(pose, filter.particles()).await;
                                     std::mem::drop(filter);
-                                    send_async!(Event::PoseUpdated(pose.into()) => robot.event)
-                                        .await;
-                                    robot.automatic(pose).await;
+                                    // 以 GNSS 校正后的滤波器位姿作为实际运动
+                                    if let GpggaStatus::浮点解 | GpggaStatus::固定解 = gpgga.status
+                                    {
+                                        let fault = robot
+                                            .stuck
+                                            .lock()
+                                            .await
+                                            .update_pose(t, pose.translation.vector.into());
+                                        if let Some(fault) = fault {
+                                            robot.motion_fault(fault).await;
+                                        }
+                                    }
+                                    robot.pose_updated(pose).await;
                                 }
                             }
                         }
@@ -192,6 +440,199 @@ impl Robot {
                 }
             });
         }
+        {
+            let robot = robot.clone();
+            let filter = filter.clone();
+            task::spawn(async move {
+                while let Ok(command) = from_robot.recv().await {
+                    match command {
+                        Localization::SetPose(pose, sigma) => {
+                            let mut filter = filter.lock().await;
+                            // 未初始化的滤波器先以信标位置初始化
+                            if filter.particles().is_empty() {
+                                let beacon = pose * filter.parameters.beacon_on_robot;
+                                filter.measure(Instant::now() - time_origin, beacon, sigma);
+                            }
+                            scatter!(filter, pose, sigma);
+                            std::mem::drop(filter);
+                            robot
+                                .reset_convergence(MeasurementSource::Manual, Instant::now())
+                                .await;
+                            send_async!(Event::Relocalized(pose.into()) => robot.event).await;
+                        }
+                        Localization::Calibrated(calibration) => {
+                            let mut filter = filter.lock().await;
+                            let model = filter.parameters.default_model.clone();
+                            let beacon = filter.parameters.beacon_on_robot;
+                            let result = calibration.finish(model.length, beacon);
+                            if let Ok(ref r) = result {
+                                let model = Pm1Model::new(r.width, model.length, r.wheel);
+                                filter.parameters.default_model = model.clone();
+                                std::mem::drop(filter);
+                                robot.chassis.update_model(model.clone()).await;
+                                robot.chassis.set_rudder_offset(r.rudder_offset);
+                                // 立即保存标定结果
+                                let path = robot.context_file(STATE_FILE);
+                                let mut state = SavedState::load(path.as_path()).await;
+                                state.model = Some(model);
+                                state.rudder_offset = Some(r.rudder_offset);
+                                let _ = state.save(path.as_path()).await;
+                            }
+                            send_async!(Event::CalibrationFinished(result) => robot.event).await;
+                        }
+                    }
+                }
+            });
+        }
+        {
+            let robot = robot.clone();
+            task::spawn(async move {
+                loop {
+                    task::sleep(MAPPING_PERIOD).await;
+                    let pose = *robot.pose.lock().await;
+                    let mut mapping = robot.mapping.lock().await;
+                    if let (Some(grid), Some(pose)) = (&mut *mapping, pose) {
+                        for (origin, points) in robot.lidar.scans().await {
+                            let points = points.into_iter().map(|p| pose * p).collect::<Vec<_>>();
+                            grid.insert(pose * origin, &points);
+                        }
+                    }
+                }
+            });
+        }
+        {
+            let robot = robot.clone();
+            let filter = filter.clone();
+            task::spawn(async move {
+                // 上次加权时的轮速计位姿
+                let mut last: Option<Isometry2<f32>> = None;
+                loop {
+                    task::sleep(MAP_LOCALIZATION_PERIOD).await;
+                    // 取出地图后立即释放，加权时不同时持有地图和滤波器
+                    let field = match robot.map.lock().await.clone() {
+                        Some(field) => field,
+                        None => continue,
+                    };
+                    // GNSS 固定解时不需要地图
+                    let gain = match map_gain(*robot.rtk_status.lock().await) {
+                        Some(gain) => gain,
+                        None => continue,
+                    };
+                    let (_, scan) = robot.lidar.snapshot().await;
+                    let odometry = robot.odometry.lock().await.latest();
+                    if scan.is_empty() {
+                        continue;
+                    }
+                    // 静止时重复加权会使滤波器过度自信，移动足够远才再次加权
+                    if let Some(last) = last {
+                        let delta = last.inverse() * odometry;
+                        if delta.translation.vector.norm() < MAP_LOCALIZATION_DISTANCE
+                            && delta.rotation.angle().abs() < MAP_LOCALIZATION_ANGLE
+                        {
+                            continue;
+                        }
+                    }
+                    last = Some(odometry);
+                    let step = scan.len() / MAP_LOCALIZATION_POINTS + 1;
+                    let scan = scan.into_iter().step_by(step).collect::<Vec<_>>();
+                    // 以似然场为粒子加权，GNSS 越差地图权重越高
+                    let mut filter = filter.lock().await;
+                    let updated = reweight(filter.particles_mut(), |p| {
+                        let l = field.likelihood(scan.iter().map(|q| p.pose * q));
+                        l.powf(gain)
+                    });
+                    std::mem::drop(filter);
+                    if !updated {
+                        continue;
+                    }
+                    robot
+                        .quality
+                        .lock()
+                        .await
+                        .measured(MeasurementSource::Map, Instant::now());
+                }
+            });
+        }
+        {
+            let robot = robot.clone();
+            let filter = filter.clone();
+            task::spawn(async move {
+                let mut matcher = ScanMatcher::default();
+                // 上一帧点云时刻的滤波器位姿
+                let mut anchor: Option<Isometry2<f32>> = None;
+                loop {
+                    task::sleep(SCAN_MATCHING_PERIOD).await;
+                    let (time, scan) = robot.lidar.snapshot().await;
+                    let time = match time {
+                        Some(time) => time,
+                        None => continue,
+                    };
+                    // 点云时刻和当前的轮速计位姿
+                    let (odometry, current) = {
+                        let history = robot.odometry.lock().await;
+                        (history.at(time), history.latest())
+                    };
+                    let m = matcher.update(&scan, odometry);
+                    let status = *robot.rtk_status.lock().await;
+                    let mut filter = filter.lock().await;
+                    let pose = filter.get().map(|pose| pose * current.inverse() * odometry);
+                    let (last, m) = match (std::mem::replace(&mut anchor, pose), m) {
+                        (Some(last), Some(m)) => (last, m),
+                        _ => continue,
+                    };
+                    // RTK 不可靠时，以匹配得到的位姿作为滤波器的观测
+                    if !matches!(status, GpggaStatus::浮点解 | GpggaStatus::固定解) {
+                        // 上一帧位姿经匹配增量推到点云时刻，再经轮速计推到当前
+                        let measured = last * m.delta * odometry.inverse() * current;
+                        let beacon = measured * filter.parameters.beacon_on_robot;
+                        filter.measure(
+                            Instant::now() - time_origin,
+                            beacon,
+                            SCAN_MATCHING_SIGMA / m.quality,
+                        );
+                        std::mem::drop(filter);
+                        robot
+                            .quality
+                            .lock()
+                            .await
+                            .measured(MeasurementSource::ScanMatching, Instant::now());
+                    } else {
+                        std::mem::drop(filter);
+                    }
+                    send_async!(Event::ScanMatched(m) => robot.event).await;
+                }
+            });
+        }
+        {
+            let robot = robot.clone();
+            let filter = filter.clone();
+            task::spawn(async move {
+                loop {
+                    task::sleep(QUALITY_PERIOD).await;
+                    if robot.dead_reckoning.lock().await.is_some() {
+                        continue;
+                    }
+                    let sampling = *robot.sampling.lock().await;
+                    let mut filter = filter.lock().await;
+                    let stats = statistics(filter.particles());
+                    let count = filter.particles().len();
+                    // 粒子越分散，下次重采样的粒子越多
+                    if count > 0 {
+                        filter.parameters.count = sampling.count(filter.particles());
+                    }
+                    let target = filter.parameters.count;
+                    std::mem::drop(filter);
+                    let mut monitor = robot.quality.lock().await;
+                    let (quality, changed) = monitor.update(stats, count, target, Instant::now());
+                    std::mem::drop(monitor);
+                    send_async!(Event::LocalizationQuality(quality) => robot.event).await;
+                    if changed {
+                        send_async!(Event::ConvergenceChanged(quality.convergence) => robot.event)
+                            .await;
+                    }
+                }
+            });
+        }
         {
             let robot = robot.clone();
             task::spawn(async move {
@@ -229,14 +670,31 @@ impl Robot {
                             let odom = model.wheels_to_velocity(wheels).to_odometry();
                             s += odom.s;
                             a += odom.a;
+                            robot.odometry.lock().await.push(t, odom.pose);
                             send_async!(Event::ChassisOdometerUpdated(s, a) => robot.event).await;
                             robot.chassis.update_model(model).await;
-                            if let Some(pose) = filter.get() {
+                            let target = robot.chassis.commanded().await;
+                            let fault = robot.stuck.lock().await.update_wheels(t, target, odom.s);
+                            if let Some(fault) = fault {
+                                robot.motion_fault(fault).await;
+                            }
+                            if let Task::Calibrate(c) = &mut *robot.task.lock().await {
+                                c.update_wheels(wheels);
+                            }
+                            // 航位推算模式
+                            let mut dead_reckoning = robot.dead_reckoning.lock().await;
+                            if let Some(ref mut pose) = *dead_reckoning {
+                                *pose *= odom.pose;
+                                let pose = *pose;
+                                std::mem::drop(dead_reckoning);
+                                std::mem::drop(filter);
+                                robot.pose_updated(pose).await;
+                            } else if let Some(pose) = filter.get() {
+                                std::mem::drop(dead_reckoning);
                                 #[cfg(feature = "display")]
                                 robot.painter.paint_filter(pose, filter.particles()).await;
                                 std::mem::drop(filter);
-                                send_async!(Event::PoseUpdated(pose.into()) => robot.event).await;
-                                robot.automatic(pose).await;
+                                robot.pose_updated(pose).await;
                             }
                         }
                     }
@@ -257,21 +715,51 @@ impl Robot {
                 }
             });
         }
+        {
+            let robot = robot.clone();
+            task::spawn(async move {
+                // 尚未收到数据的雷达从启动起就视为失明
+                for i in [lidar::REAR, lidar::FRONT] {
+                    if robot.lidar.is_blind(i) {
+                        send_async!(Event::LidarDegraded(i, true) => robot.event).await;
+                    }
+                }
+                loop {
+                    for (i, blind) in robot.lidar.update_blind().await {
+                        send_async!(Event::LidarDegraded(i, blind) => robot.event).await;
+                    }
+                    robot.drive_blocking.check().await;
+                    task::sleep(Duration::from_millis(100)).await;
+                }
+            });
+        }
         {
             let robot = robot.clone();
             task::spawn_blocking(move || {
-                let mut joystick = joystick::Joystick::new();
+                let mut joystick = Joystick::new();
                 loop {
-                    let target = joystick.get();
+                    let profile = *task::block_on(robot.joystick_profile.lock());
+                    let target = joystick.get(&profile);
+                    let pressed = joystick.pressed();
                     task::block_on(async {
-                        if !target.is_released() {
-                            join!(
-                                robot.drive_blocking.drive_joystick(),
-                                robot.drive_and_warn(target, 0.0),
-                                task::sleep(Duration::from_millis(50)),
-                            );
+                        for button in pressed {
+                            robot.gamepad_pressed(button).await;
+                        }
+                        if robot.share_joystick(target).await {
+                            task::sleep(Duration::from_millis(50)).await;
+                        } else if !target.is_released() {
+                            if let Ok(true) = robot.drive_blocking.try_drive(JOYSTICK).await {
+                                robot.chassis.store_raw_target(target).await;
+                                join!(
+                                    robot.drive_and_warn(target, 0.0),
+                                    task::sleep(Duration::from_millis(50)),
+                                );
+                            } else {
+                                task::sleep(Duration::from_millis(50)).await;
+                            }
                         } else {
-                            task::sleep(Duration::from_millis(400)).await;
+                            // 空闲时也要及时响应按键，尤其是急停
+                            task::sleep(Duration::from_millis(50)).await;
                         }
                     });
                 }
@@ -287,6 +775,88 @@ impl Robot {
         self.painter.connect(a).await;
     }
 
+    /// 设置加减速、转向速度和最大速度约束
+    #[inline]
+    pub async fn set_motion_profile(&self, profile: MotionProfile) {
+        self.chassis.set_profile(profile).await;
+    }
+
+    /// 检查差分服务账号格式，账号形如 `用户名:密码`
+    #[inline]
+    pub fn validate_rtk_credential(&self, account: &str) -> Result<(), CredentialError> {
+        rtk::validate_credential(account)
+    }
+
+    /// 保存差分服务账号并立即以新账号重连，`expires` 为账号到期时间
+    #[inline]
+    pub async fn set_rtk_credential(
+        &self,
+        account: &str,
+        expires: Option<std::time::SystemTime>,
+    ) -> Result<(), CredentialError> {
+        rtk::set_credential(self.context_root(), account, expires).await
+    }
+
+    /// 删除差分服务账号
+    #[inline]
+    pub async fn clear_rtk_credential(&self) -> async_std::io::Result<()> {
+        rtk::clear_credential(self.context_root()).await
+    }
+
+    /// 设置 GNSS 观测模型
+    #[inline]
+    pub async fn set_gnss_model(&self, model: GnssModel) {
+        *self.gnss_model.lock().await = model;
+    }
+
+    /// 最近一次计算的定位质量
+    #[inline]
+    pub async fn localization_quality(&self) -> Option<LocalizationQuality> {
+        self.quality.lock().await.quality()
+    }
+
+    /// 设置粒子数自适应范围
+    #[inline]
+    pub async fn set_adaptive_sampling(&self, sampling: AdaptiveSampling) {
+        *self.sampling.lock().await = sampling;
+    }
+
+    /// 设置循迹和录制对定位质量的要求
+    #[inline]
+    pub async fn set_quality_gate(&self, gate: QualityGate) {
+        self.gate.lock().await.config = gate;
+    }
+
+    /// 设置卡滞和打滑检测参数
+    #[inline]
+    pub async fn set_stuck_config(&self, config: StuckConfig) {
+        self.stuck.lock().await.config = config;
+    }
+
+    /// 设置负载在机器人坐标系中的轮廓，雷达将忽略其中的点
+    #[inline]
+    pub async fn set_payload(&self, shapes: &[Vec<(f32, f32)>]) {
+        self.lidar.set_payload(shapes).await;
+    }
+
+    /// 设置手柄按键绑定，一个按键可以绑定多个动作
+    #[inline]
+    pub async fn set_gamepad_bindings(&self, bindings: Vec<(GamepadButton, GamepadAction)>) {
+        *self.bindings.lock().await = bindings;
+    }
+
+    /// 切换手柄响应曲线
+    #[inline]
+    pub async fn set_joystick_profile(&self, profile: JoystickProfile) {
+        *self.joystick_profile.lock().await = profile;
+    }
+
+    /// 设置可由手柄切换的循迹速度档位
+    #[inline]
+    pub async fn set_tracking_speed_presets(&self, presets: Vec<f32>) {
+        *self.speed_presets.lock().await = (presets, 0);
+    }
+
     #[inline]
     pub fn set_tracking_speed(&self, val: f32) {
         self.tracking_speed.store(val.to_bits(), Relaxed);
@@ -300,6 +870,23 @@ impl Robot {
             .map(|f| f.collect())
     }
 
+    /// 开始标定底盘模型
+    ///
+    /// 机器人将依次走直线、左弧、右弧、直线，需要 RTK 固定解、雷达正常和约 20 m 的开阔场地。
+    /// 结果通过 [`Event::CalibrationFinished`] 报告，成功时立即应用并保存。
+    /// 途中雷达失明、避障或其他控制源介入时标定失败。
+    pub async fn calibrate(&self) -> Result<(), CalibrationError> {
+        if *self.rtk_status.lock().await != GpggaStatus::固定解 {
+            return Err(CalibrationError::PoorGnss);
+        }
+        if self.lidar.any_blind() {
+            return Err(CalibrationError::LidarBlind);
+        }
+        let offset = self.chassis.rudder_offset();
+        *self.task.lock().await = Task::Calibrate(Calibration::new(Instant::now(), offset));
+        Ok(())
+    }
+
     #[inline]
     pub async fn record(&self) {
         *self.task.lock().await = Task::WaitingPose;
@@ -323,12 +910,59 @@ impl Robot {
                 r#loop: false,
             }),
         );
+        *self.shared.lock().await = None;
+        self.gate.lock().await.reset();
+        Ok(())
+    }
+
+    /// 以共享控制模式循迹
+    ///
+    /// 手柄推动时以手柄速度行驶，并按手柄转向偏离路径至多 `max_offset` 米；
+    /// 松开手柄后回到路径上继续循迹。
+    pub async fn track_shared(&self, max_offset: f32) -> async_std::io::Result<()> {
+        self.track().await?;
+        *self.shared.lock().await = Some(SharedControl {
+            max_offset: max_offset.abs(),
+            input: None,
+        });
         Ok(())
     }
 
     #[inline]
     pub async fn stop(&self) {
         *self.task.lock().await = Task::Idle;
+        *self.shared.lock().await = None;
+    }
+
+    /// 触发急停，锁定直到显式复位
+    ///
+    /// 无论锁定状态能否保存，急停都会立即生效；保存失败时返回错误，重启后不会恢复锁定
+    pub async fn emergency_stop(&self) -> async_std::io::Result<()> {
+        if self.chassis.set_emergency_stop(true).await {
+            // 急停同时终止循迹，复位后不会自行恢复
+            let mut task = self.task.lock().await;
+            if matches!(*task, Task::Track(_, _) | Task::Calibrate(_)) {
+                *task = Task::Idle;
+            }
+            std::mem::drop(task);
+            *self.shared.lock().await = None;
+            send_async!(Event::EmergencyStopTriggered => self.event).await;
+        }
+        // 已锁定时也重新保存，上次保存失败可以重试
+        async_std::fs::write(self.context_file(EMERGENCY_STOP_FILE), b"").await
+    }
+
+    /// 复位急停
+    pub async fn reset_emergency_stop(&self) {
+        if self.chassis.set_emergency_stop(false).await {
+            let _ = async_std::fs::remove_file(self.context_file(EMERGENCY_STOP_FILE)).await;
+            send_async!(Event::EmergencyStopReset => self.event).await;
+        }
+    }
+
+    #[inline]
+    pub fn is_emergency_stopped(&self) -> bool {
+        self.chassis.is_emergency_stopped()
     }
 
     #[inline]
@@ -336,16 +970,232 @@ impl Robot {
         self.chassis.predict().await
     }
 
+    #[inline]
     pub async fn drive(&self, target: Physical) {
-        if self.drive_blocking.try_drive_artificial().await {
+        let _ = self.drive_as(ARTIFICIAL, target).await;
+    }
+
+    /// 注册控制源，租期内阻止所有优先级不高于它的其他控制源
+    ///
+    /// 内置控制源的优先级：手柄 200，人工 100，自动 0；自动控制不阻止其他控制源
+    #[inline]
+    pub async fn register_control_source(
+        &self,
+        name: &str,
+        priority: u32,
+        lease: Duration,
+    ) -> ControlSource {
+        self.drive_blocking
+            .register(name.into(), priority, lease)
+            .await
+    }
+
+    /// 注销控制源，内置控制源不能注销
+    #[inline]
+    pub async fn unregister_control_source(
+        &self,
+        source: ControlSource,
+    ) -> Result<(), ControlSourceError> {
+        self.drive_blocking.unregister(source).await
+    }
+
+    /// 以指定控制源控制，被更高优先级的控制源阻止时忽略
+    pub async fn drive_as(
+        &self,
+        source: ControlSource,
+        target: Physical,
+    ) -> Result<(), ControlSourceError> {
+        if self.drive_blocking.try_drive(source).await? {
             self.check_and_drive(target).await;
         }
+        Ok(())
+    }
+
+    /// 当前掌握控制权的控制源
+    #[inline]
+    pub async fn control_source(&self) -> Option<String> {
+        self.drive_blocking.current().await
+    }
+
+    /// 标记当前位置为航点
+    pub async fn mark_waypoint(&self) -> Option<Pose> {
+        use async_std::{fs::OpenOptions, io::WriteExt};
+
+        let pose = Pose::from((*self.pose.lock().await)?);
+        if let Ok(mut file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.context_file(WAYPOINTS_FILE))
+            .await
+        {
+            let line = format!("{} {} {}\n", pose.x, pose.y, pose.theta);
+            let _ = file.write_all(line.as_bytes()).await;
+        }
+        send_async!(Event::WaypointMarked(pose) => self.event).await;
+        Some(pose)
+    }
+
+    /// 共享控制循迹时记录手柄输入，返回是否已被共享控制接管
+    async fn share_joystick(&self, target: Physical) -> bool {
+        if !matches!(*self.task.lock().await, Task::Track(_, _)) {
+            return false;
+        }
+        match &mut *self.shared.lock().await {
+            Some(shared) => {
+                shared.input = if target.is_released() {
+                    None
+                } else {
+                    Some((Instant::now(), target))
+                };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 执行手柄按键绑定的动作
+    async fn gamepad_pressed(&self, button: GamepadButton) {
+        let actions = self
+            .bindings
+            .lock()
+            .await
+            .iter()
+            .filter(|(b, _)| *b == button)
+            .map(|(_, a)| *a)
+            .collect::<Vec<_>>();
+        for action in actions {
+            use GamepadAction::*;
+            match action {
+                ToggleRecording => {
+                    let mut task = self.task.lock().await;
+                    *task = match *task {
+                        Task::Idle => Task::WaitingPose,
+                        Task::WaitingPose | Task::Record(_) => Task::Idle,
+                        // 循迹或标定中不打断
+                        Task::Track(_, _) | Task::Calibrate(_) => continue,
+                    };
+                }
+                ToggleTracking => {
+                    let tracking = matches!(*self.task.lock().await, Task::Track(_, _));
+                    if tracking {
+                        self.stop().await;
+                    } else if self.track().await.is_err() {
+                        continue;
+                    }
+                }
+                EmergencyStop => {
+                    if let Err(e) = self.emergency_stop().await {
+                        eprintln!("failed to persist emergency stop: {}", e);
+                    }
+                }
+                CycleSpeedPreset => {
+                    let mut presets = self.speed_presets.lock().await;
+                    let (ref list, ref mut i) = *presets;
+                    if list.is_empty() {
+                        continue;
+                    }
+                    *i = (*i + 1) % list.len();
+                    self.set_tracking_speed(list[*i]);
+                }
+                MarkWaypoint => {
+                    if self.mark_waypoint().await.is_none() {
+                        continue;
+                    }
+                }
+            }
+            send_async!(Event::GamepadActionTriggered(action) => self.event).await;
+        }
+    }
+
+    /// 发布新的位姿并执行任务
+    async fn pose_updated(&self, pose: Isometry2<f32>) {
+        *self.pose.lock().await = Some(pose);
+        send_async!(Event::PoseUpdated(pose.into()) => self.event).await;
+        self.automatic(pose).await;
+    }
+
+    /// 开始建图，`resolution` 为栅格边长（m）
+    #[inline]
+    pub async fn start_mapping(&self, resolution: f32) {
+        *self.mapping.lock().await = Some(OccupancyGrid::new(resolution));
+    }
+
+    /// 结束建图并保存到上下文目录
+    pub async fn stop_mapping(&self) -> async_std::io::Result<()> {
+        match self.mapping.lock().await.take() {
+            Some(grid) => grid.save(self.context_root()).await,
+            None => Ok(()),
+        }
+    }
+
+    /// 手动设置位姿
+    ///
+    /// 粒子在给定位姿附近重新撒布，`sigma` 同时作为位置（m）和方向（rad）的标准差。
+    /// 航位推算模式下直接设置位姿。
+    pub async fn set_pose(&self, pose: Pose, sigma: f32) {
+        if self.set_dead_reckoning_pose(pose).await {
+            send_async!(Event::Relocalized(pose) => self.event).await;
+        } else {
+            let _ = self
+                .localization
+                .send(Localization::SetPose(pose.into(), sigma))
+                .await;
+        }
+    }
+
+    /// 机器人已放在路径起点时，重定位到路径起点
+    pub async fn relocalize_to_path_start(&self, sigma: f32) -> async_std::io::Result<()> {
+        use async_std::io::{Error, ErrorKind};
+
+        let first = PathFile::open(self.context_dir.as_path())
+            .await?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "empty path"))?;
+        self.set_pose(first.into(), sigma).await;
+        Ok(())
+    }
+
+    /// 设置航位推算的当前位姿，仅在没有 RTK 时有效
+    pub async fn set_dead_reckoning_pose(&self, pose: Pose) -> bool {
+        match *self.dead_reckoning.lock().await {
+            Some(ref mut current) => {
+                *current = pose.into();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 从上下文目录加载地图，用于辅助定位
+    pub async fn load_map(&self) -> async_std::io::Result<()> {
+        let field = LikelihoodField::load(self.context_root()).await?;
+        *self.map.lock().await = Some(Arc::new(field));
+        Ok(())
+    }
+
+    #[inline]
+    pub async fn unload_map(&self) {
+        *self.map.lock().await = None;
+    }
+
+    /// 上下文目录
+    #[inline]
+    fn context_root(&self) -> &async_std::path::Path {
+        self.context_dir.parent().unwrap()
+    }
+
+    /// 上下文目录中的其他文件
+    #[inline]
+    fn context_file(&self, name: &str) -> PathBuf {
+        self.context_dir.with_file_name(name)
     }
 
     async fn automatic(&self, pose: Isometry2<f32>) {
+        let quality = self.quality.lock().await.quality();
         let mut task = self.task.lock().await;
         match &mut *task {
             Task::Idle => {}
+            Task::WaitingPose | Task::Record(_) if !self.recording_allowed(quality).await => {}
             Task::WaitingPose => {
                 if let Ok(r) = RecordFile::new(self.context_dir.as_path(), pose).await {
                     *task = Task::Record(r);
@@ -361,19 +1211,81 @@ impl Robot {
                     )
                 }
             }
+            Task::Calibrate(calibration) => match calibration.target(Instant::now()) {
+                // 失明时的限速会改变控制目标，不能继续标定
+                Some(_) if self.lidar.any_blind() => calibration.lidar_blind(),
+                Some(target) => {
+                    let applied = match self.drive_blocking.try_drive(AUTOMATIC).await {
+                        Ok(true) => Some(self.check_and_drive(target).await),
+                        _ => None,
+                    };
+                    calibration.applied(Instant::now(), applied);
+                }
+                None => {
+                    if let Task::Calibrate(c) = std::mem::replace(&mut *task, Task::Idle) {
+                        std::mem::drop(task);
+                        self.chassis.drive(Physical::RELEASED).await;
+                        let _ = self.localization.send(Localization::Calibrated(c)).await;
+                    }
+                }
+            },
             Task::Track(path, context) => {
-                if self.drive_blocking.try_drive_automatic().await {
+                // 定位质量不足时减速、暂停或放弃，航位推算模式下没有质量评估
+                let (action, restriction) = if self.dead_reckoning.lock().await.is_some() {
+                    (GateAction::Drive(f32::INFINITY), None)
+                } else {
+                    self.gate.lock().await.check(quality, Instant::now())
+                };
+                if let Some(restriction) = restriction {
+                    send_async!(Event::TrackingRestricted(restriction) => self.event).await;
+                }
+                let max_speed = match action {
+                    GateAction::Drive(max_speed) => max_speed,
+                    GateAction::Pause => {
+                        if let Ok(true) = self.drive_blocking.try_drive(AUTOMATIC).await {
+                            self.drive_and_warn(Physical::RELEASED, 0.0).await;
+                        }
+                        return;
+                    }
+                    GateAction::Abort => {
+                        *task = Task::Idle;
+                        std::mem::drop(task);
+                        self.chassis.drive(Physical::RELEASED).await;
+                        return;
+                    }
+                };
+                if let Ok(true) = self.drive_blocking.try_drive(AUTOMATIC).await {
                     let clone = context.clone();
                     let mut tracker = Tracker {
                         path,
                         context: clone,
                     };
+                    // 共享控制的手柄输入
+                    let (speed, offset) = match &*self.shared.lock().await {
+                        Some(SharedControl {
+                            max_offset,
+                            input: Some((time, input)),
+                        }) if time.elapsed() < SHARED_INPUT_TIMEOUT => (
+                            f32::max(input.speed, 0.0),
+                            (input.rudder / FRAC_PI_2).clamp(-1.0, 1.0) * max_offset,
+                        ),
+                        _ => (f32::from_bits(self.tracking_speed.load(Relaxed)), 0.0),
+                    };
+                    // 偏移后的机器人视为在路径上
+                    let pose = pose * Isometry2::translation(0.0, -offset);
                     if let Ok((k, rudder)) = tracker.track(pose) {
-                        self.check_and_drive(Physical {
-                            speed: f32::from_bits(self.tracking_speed.load(Relaxed)) * k,
-                            rudder,
-                        })
-                        .await;
+                        let speed = (speed * k).clamp(-max_speed, max_speed);
+                        // 不向失明的方向自动行驶
+                        let side = if speed > 0.0 {
+                            lidar::FRONT
+                        } else {
+                            lidar::REAR
+                        };
+                        if speed != 0.0 && self.lidar.is_blind(side) {
+                            self.drive_and_warn(Physical::RELEASED, 0.0).await;
+                        } else {
+                            self.check_and_drive(Physical { speed, rudder }).await;
+                        }
                     }
                     *context = tracker.context;
                 }
@@ -381,12 +1293,50 @@ impl Robot {
         }
     }
 
-    async fn check_and_drive(&self, mut p: Physical) {
+    /// 重新初始化滤波器后，定位需要重新收敛，循迹随之暂停
+    async fn reset_convergence(&self, source: MeasurementSource, t: Instant) {
+        let mut quality = self.quality.lock().await;
+        let changed = quality.reset();
+        quality.measured(source, t);
+        std::mem::drop(quality);
+        if changed {
+            send_async!(Event::ConvergenceChanged(Convergence::Converging) => self.event).await;
+        }
+    }
+
+    /// 定位质量是否足以录制，航位推算模式下没有质量评估
+    async fn recording_allowed(&self, quality: Option<LocalizationQuality>) -> bool {
+        match quality {
+            Some(q) => self.gate.lock().await.config.allows_recording(&q),
+            None => self.dead_reckoning.lock().await.is_some(),
+        }
+    }
+
+    /// 处理卡滞或打滑
+    async fn motion_fault(&self, fault: MotionFault) {
+        if self.stuck.lock().await.config.stop_tracking {
+            let mut task = self.task.lock().await;
+            if matches!(*task, Task::Track(_, _) | Task::Calibrate(_)) {
+                *task = Task::Idle;
+                std::mem::drop(task);
+                self.chassis.drive(Physical::RELEASED).await;
+            }
+        }
+        let event = match fault {
+            MotionFault::Stuck => Event::Stuck,
+            MotionFault::Slipping => Event::Slipping,
+        };
+        send_async!(event => self.event).await;
+    }
+
+    /// 避障后控制，返回实际执行的控制目标
+    async fn check_and_drive(&self, mut p: Physical) -> Physical {
+        let original = p;
         // 保存目标状态
         self.chassis.store_raw_target(p).await;
         // 目标是静止不动
         if p.is_static() {
-            self.drive_and_warn(p, 0.0).await;
+            self.drive_and_warn(p, 0.0).await
         }
         // 可能碰撞
         else if let Some(collision) = {
@@ -397,7 +1347,8 @@ impl Robot {
             }
         } {
             let (p, r) = if collision.pose.s < 0.2 && collision.pose.a < FRAC_PI_8 {
-                // 将在极小距离内碰撞
+                // 将在极小距离内碰撞，紧急制动
+                self.chassis.brake().await;
                 (Physical::RELEASED, 1.0)
             } else {
                 // 一般碰撞
@@ -415,20 +1366,33 @@ impl Robot {
 
                 (p, f32::min(1.0, (2.0 - sec) * collision.risk))
             };
-            self.drive_and_warn(p, r).await;
+            let (p, _) = join!(
+                self.drive_and_warn(p, r),
+                send_async!(Event::CollisionAvoiding(Avoidance {
+                    collision,
+                    original,
+                    modified: p,
+                }) => self.event),
+            );
+            p
         }
         // 不可能碰撞
         else {
-            self.drive_and_warn(p, 0.0).await;
+            self.drive_and_warn(p, 0.0).await
         }
     }
 
     #[inline]
-    async fn drive_and_warn(&self, p: Physical, r: f32) {
+    async fn drive_and_warn(&self, mut p: Physical, r: f32) -> Physical {
+        // 雷达失明时限速
+        if self.lidar.any_blind() {
+            p.speed = p.speed.clamp(-DEGRADED_SPEED, DEGRADED_SPEED);
+        }
         join!(
             self.chassis.drive(p),
             send_async!(Event::CollisionDetected(r) => self.event),
         );
+        p
     }
 }
 
@@ -456,6 +1420,17 @@ mod macros {
     pub(crate) use {join_async, send_async};
 }
 
+/// 地图似然的权重指数，GNSS 定位越差地图越占主导，固定解时不使用地图
+#[inline]
+fn map_gain(status: GpggaStatus) -> Option<f32> {
+    match status {
+        GpggaStatus::固定解 => None,
+        GpggaStatus::浮点解 => Some(2.0),
+        GpggaStatus::伪距差分 | GpggaStatus::单点解 => Some(6.0),
+        _ => Some(8.0),
+    }
+}
+
 #[inline]
 pub(crate) const fn vector(x: f32, y: f32) -> Vector2<f32> {
     use parry2d::na::{ArrayStorage, Vector};
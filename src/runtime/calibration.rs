@@ -0,0 +1,319 @@
+﻿use parry2d::na::{Point2, Vector2};
+use pm1_sdk::model::{Physical, Wheels};
+use std::{
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+const SETTLE: Duration = Duration::from_millis(1500); // 每段开始时等待舵轮和速度稳定
+const MIN_SEGMENTS: usize = 3; // 每段至少需要的 GNSS 间隔数
+
+/// 标定路线：直线、左弧、右弧、直线
+const LEGS: [(f32, f32, Duration); 4] = [
+    (0.5, 0.0, Duration::from_secs(10)),
+    (0.5, 0.4, Duration::from_secs(10)),
+    (0.5, -0.4, Duration::from_secs(10)),
+    (0.5, 0.0, Duration::from_secs(10)),
+];
+
+/// 底盘标定结果
+#[derive(Clone, Copy, Debug)]
+pub struct CalibrationResult {
+    /// 轮间距（m）
+    pub width: f32,
+    /// 轮半径（m）
+    pub wheel: f32,
+    /// 舵轮零偏（rad），叠加到控制目标上
+    pub rudder_offset: f32,
+    /// 每段里程的均方根残差（m）
+    pub distance_residual: f32,
+    /// 每段转角的均方根残差（rad）
+    pub heading_residual: f32,
+    /// 使用的 GNSS 间隔数
+    pub samples: usize,
+}
+
+/// 标定失败的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CalibrationError {
+    /// 开始时没有 RTK 固定解，或标定过程中失去固定解
+    PoorGnss,
+    /// 雷达失明，限速会改变控制目标
+    LidarBlind,
+    /// 控制目标被避障修改
+    Obstructed,
+    /// 其他控制源接管了控制
+    Interrupted,
+    /// 有效数据不足
+    TooFewSamples,
+    /// 数据无法确定参数，例如舵轮没有转动
+    Degenerate,
+}
+
+/// 一段标定路线的数据
+struct Leg {
+    rudder: f32,
+    /// GNSS 位移和期间左右轮转角
+    segments: Vec<(Vector2<f32>, f32, f32)>,
+}
+
+/// 进行中的标定
+pub(super) struct Calibration {
+    start: Instant,
+    rudder_offset: f32,
+    legs: Vec<Leg>,
+    last: Option<Point2<f32>>,
+    wheels: (f32, f32),
+    failed: Option<CalibrationError>,
+}
+
+impl Calibration {
+    /// 开始标定，`rudder_offset` 为底盘当前使用的舵轮零偏
+    pub fn new(start: Instant, rudder_offset: f32) -> Self {
+        Self {
+            start,
+            rudder_offset,
+            legs: LEGS
+                .iter()
+                .map(|(_, rudder, _)| Leg {
+                    rudder: *rudder,
+                    segments: Vec::new(),
+                })
+                .collect(),
+            last: None,
+            wheels: (0.0, 0.0),
+            failed: None,
+        }
+    }
+
+    /// 当前时刻的控制目标，路线走完返回 `None`
+    pub fn target(&self, now: Instant) -> Option<Physical> {
+        if self.failed.is_some() {
+            return None;
+        }
+        self.leg_at(now).map(|(i, _)| Physical {
+            speed: LEGS[i].0,
+            rudder: LEGS[i].1,
+        })
+    }
+
+    /// 检查实际执行的控制目标，`None` 表示未取得控制权
+    ///
+    /// 与路线不符时该段数据无效，标定失败
+    pub fn applied(&mut self, now: Instant, applied: Option<Physical>) {
+        let target = match self.target(now) {
+            Some(target) => target,
+            None => return,
+        };
+        match applied {
+            Some(p)
+                if (p.speed - target.speed).abs() < 1e-3
+                    && (p.rudder - target.rudder).abs() < 1e-3 => {}
+            Some(_) => {
+                self.failed.get_or_insert(CalibrationError::Obstructed);
+            }
+            None => {
+                self.failed.get_or_insert(CalibrationError::Interrupted);
+            }
+        }
+    }
+
+    /// 雷达失明，标定失败
+    #[inline]
+    pub fn lidar_blind(&mut self) {
+        self.failed.get_or_insert(CalibrationError::LidarBlind);
+    }
+
+    /// 累计轮速计
+    #[inline]
+    pub fn update_wheels(&mut self, wheels: Wheels) {
+        self.wheels.0 += wheels.left;
+        self.wheels.1 += wheels.right;
+    }
+
+    /// 记录一次 GNSS 观测，`p` 为非固定解时为 `None`
+    pub fn update_gnss(&mut self, t: Instant, p: Option<Point2<f32>>) {
+        let leg = self.leg_at(t).filter(|(_, elapsed)| *elapsed > SETTLE);
+        match (leg, p) {
+            (Some(_), None) => {
+                self.failed.get_or_insert(CalibrationError::PoorGnss);
+            }
+            (Some((i, _)), Some(p)) => {
+                if let Some(last) = self.last {
+                    let (left, right) = self.wheels;
+                    self.legs[i].segments.push((p - last, left, right));
+                }
+                self.last = Some(p);
+            }
+            (None, _) => self.last = None,
+        }
+        self.wheels = (0.0, 0.0);
+    }
+
+    /// 以 `length` 为前后轮距、`beacon` 为天线在机器人上的位置拟合
+    pub fn finish(
+        &self,
+        length: f32,
+        beacon: Point2<f32>,
+    ) -> Result<CalibrationResult, CalibrationError> {
+        match self.failed {
+            Some(e) => Err(e),
+            None => fit(&self.legs, length, beacon, self.rudder_offset),
+        }
+    }
+
+    /// 当前所在的段及其已进行的时间
+    fn leg_at(&self, t: Instant) -> Option<(usize, Duration)> {
+        let mut elapsed = t.saturating_duration_since(self.start);
+        for (i, (_, _, duration)) in LEGS.iter().enumerate() {
+            if elapsed < *duration {
+                return Some((i, elapsed));
+            }
+            elapsed -= *duration;
+        }
+        None
+    }
+}
+
+/// 每段的里程 D、曲率 k、转角 Θ，轮转角和 S、轮转角差 A
+///
+/// GNSS 测量的是天线 `beacon` 的轨迹，转弯时需要换算到轮轴中心
+fn summarize(leg: &Leg, beacon: Point2<f32>) -> Option<(f32, f32, f32, f32, f32)> {
+    let segments = &leg.segments;
+    let n = segments.len();
+    if n < MIN_SEGMENTS {
+        return None;
+    }
+    // 转角以位移方向的变化计，对应首末两个间隔中点之间的运动
+    let theta = segments
+        .windows(2)
+        .map(|w| {
+            let a = w[1].0[1].atan2(w[1].0[0]) - w[0].0[1].atan2(w[0].0[0]);
+            (a + PI).rem_euclid(2.0 * PI) - PI
+        })
+        .sum::<f32>();
+    // 弦长换算为弧长
+    let phi = theta / (n - 1) as f32 / 2.0;
+    let chord = if phi.abs() > 1e-6 {
+        phi / phi.sin()
+    } else {
+        1.0
+    };
+    let d = segments.iter().map(|(v, _, _)| v.norm()).sum::<f32>() * chord;
+    let d_mid = d - (segments[0].0.norm() + segments[n - 1].0.norm()) / 2.0 * chord;
+    // 天线速度与轮轴中心速度之比依赖曲率，迭代求解
+    let ratio = |k: f32| f32::hypot(1.0 - k * beacon[1], k * beacon[0]);
+    let mut k = theta / d_mid;
+    for _ in 0..3 {
+        k = theta * ratio(k) / d_mid;
+    }
+    let ratio = ratio(k);
+    let s = segments.iter().map(|(_, l, r)| (l + r) / 2.0).sum::<f32>();
+    let diff = |(_, l, r): &(Vector2<f32>, f32, f32)| r - l;
+    let a = segments.iter().map(diff).sum::<f32>()
+        - (diff(&segments[0]) + diff(&segments[n - 1])) / 2.0;
+    Some((d / ratio, k, theta, s, a))
+}
+
+fn fit(
+    legs: &[Leg],
+    length: f32,
+    beacon: Point2<f32>,
+    rudder_offset: f32,
+) -> Result<CalibrationResult, CalibrationError> {
+    let data = legs
+        .iter()
+        .filter_map(|leg| summarize(leg, beacon).map(|x| (leg.rudder, x)))
+        .collect::<Vec<_>>();
+    if data.len() < 2 {
+        return Err(CalibrationError::TooFewSamples);
+    }
+    // 轮半径：D = r * S
+    let (ds, ss) = data
+        .iter()
+        .fold((0.0, 0.0), |(ds, ss), (_, (d, _, _, s, _))| {
+            (ds + d * s, ss + s * s)
+        });
+    let wheel = ds / ss;
+    // 轮间距：Θ = r * A / width
+    let (ta, aa) = data
+        .iter()
+        .fold((0.0, 0.0), |(ta, aa), (_, (_, _, t, _, a))| {
+            (ta + t * wheel * a, aa + wheel * a * wheel * a)
+        });
+    if !wheel.is_normal() || ta.abs() < f32::EPSILON || aa < f32::EPSILON {
+        return Err(CalibrationError::Degenerate);
+    }
+    let width = aa / ta;
+    // 舵轮实际转角 atan(kL)，方向约定由数据确定
+    let sign = data
+        .iter()
+        .map(|(c, (_, k, _, _, _))| c * (k * length).atan())
+        .sum::<f32>()
+        .signum();
+    let (error, weight) = data
+        .iter()
+        .fold((0.0, 0.0), |(e, w), (c, (d, k, _, _, _))| {
+            (e + (c - sign * (k * length).atan()) * d, w + d)
+        });
+    // 残差
+    let n = data.len() as f32;
+    let (dr, tr) = data
+        .iter()
+        .fold((0.0, 0.0), |(dr, tr), (_, (d, _, t, s, a))| {
+            let ed = d - wheel * s;
+            let et = t - wheel * a / width;
+            (dr + ed * ed, tr + et * et)
+        });
+    Ok(CalibrationResult {
+        width,
+        wheel,
+        rudder_offset: rudder_offset + error / weight,
+        distance_residual: (dr / n).sqrt(),
+        heading_residual: (tr / n).sqrt(),
+        samples: legs.iter().map(|leg| leg.segments.len()).sum(),
+    })
+}
+
+#[test]
+fn test() {
+    use parry2d::na::Isometry2;
+
+    const WIDTH: f32 = 0.48;
+    const LENGTH: f32 = 0.355;
+    const WHEEL: f32 = 0.1;
+    const OFFSET: f32 = 0.02; // 实际转角比指令小的量
+    let beacon = Point2::new(-0.30, 0.15);
+    // 以 1 Hz 的 GNSS 模拟三轮车运动
+    let legs = LEGS
+        .iter()
+        .map(|(speed, rudder, _)| {
+            let w = speed * (rudder - OFFSET).tan() / LENGTH;
+            let step = Isometry2::new(
+                Vector2::new(w.sin() * speed / w, (1.0 - w.cos()) * speed / w),
+                w,
+            );
+            let mut pose = Isometry2::identity();
+            let mut last = beacon;
+            let segments = (0..6)
+                .map(|_| {
+                    pose *= step;
+                    let p = pose * beacon;
+                    let v = p - last;
+                    last = p;
+                    let l = (speed - w * WIDTH / 2.0) / WHEEL;
+                    let r = (speed + w * WIDTH / 2.0) / WHEEL;
+                    (v, l, r)
+                })
+                .collect();
+            Leg {
+                rudder: *rudder,
+                segments,
+            }
+        })
+        .collect::<Vec<_>>();
+    let result = fit(&legs, LENGTH, beacon, 0.0).unwrap();
+    assert!((result.wheel - WHEEL).abs() < 1e-3);
+    assert!((result.width - WIDTH).abs() < 5e-3);
+    assert!((result.rudder_offset - OFFSET).abs() < 2e-3);
+}
@@ -1,4 +1,8 @@
-﻿use super::{join_async, send_async, Physical, Trajectory};
+﻿use super::{
+    join_async,
+    profile::{Limiter, MotionProfile},
+    send_async, Physical, Trajectory,
+};
 use async_std::{
     channel::{unbounded, Receiver},
     sync::{Arc, Mutex},
@@ -10,10 +14,13 @@ use pm1_sdk::{
     PM1Event, PM1Status, PM1,
 };
 use std::{
-    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    f32::consts::FRAC_PI_2,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering::Relaxed},
     time::{Duration, Instant},
 };
 
+const TARGET_TIMEOUT: Duration = Duration::from_millis(500); // 控制目标有效期
+
 #[derive(Clone)]
 pub(super) struct Chassis(Arc<Inner>);
 
@@ -25,8 +32,11 @@ pub(super) enum Event {
 }
 
 struct Inner {
+    emergency_stop: AtomicBool,
     raw_target: AtomicU64,
+    rudder_offset: AtomicU32,
     target: Mutex<(Instant, Physical)>,
+    limiter: Mutex<Limiter>,
     model: Mutex<Option<Pm1Model>>,
     predictor: Mutex<Option<Trajectory>>,
 }
@@ -34,8 +44,43 @@ struct Inner {
 impl Chassis {
     #[inline]
     pub async fn drive(&self, p: Physical) {
-        let now = Instant::now();
-        *self.0.target.lock().await = (now, p);
+        // 持有目标锁时检查急停，避免覆盖急停写入的释放
+        let mut target = self.0.target.lock().await;
+        // 急停锁定时无论来源一律释放
+        let p = if self.0.emergency_stop.load(Relaxed) {
+            Physical::RELEASED
+        } else {
+            p
+        };
+        *target = (Instant::now(), p);
+    }
+
+    /// 紧急制动，不受运动约束限制
+    #[inline]
+    pub async fn brake(&self) {
+        self.0.limiter.lock().await.brake();
+        self.drive(Physical::RELEASED).await;
+    }
+
+    #[inline]
+    pub async fn set_profile(&self, profile: MotionProfile) {
+        self.0.limiter.lock().await.profile = profile;
+    }
+
+    /// 设置急停锁定状态，返回状态是否改变
+    pub async fn set_emergency_stop(&self, val: bool) -> bool {
+        let mut target = self.0.target.lock().await;
+        let changed = self.0.emergency_stop.swap(val, Relaxed) != val;
+        if val {
+            self.0.limiter.lock().await.brake();
+            *target = (Instant::now(), Physical::RELEASED);
+        }
+        changed
+    }
+
+    #[inline]
+    pub fn is_emergency_stopped(&self) -> bool {
+        self.0.emergency_stop.load(Relaxed)
     }
 
     #[inline]
@@ -43,6 +88,17 @@ impl Chassis {
         *self.0.model.lock().await = Some(m);
     }
 
+    /// 设置舵轮零偏，下发前叠加到控制目标上
+    #[inline]
+    pub fn set_rudder_offset(&self, offset: f32) {
+        self.0.rudder_offset.store(offset.to_bits(), Relaxed);
+    }
+
+    #[inline]
+    pub fn rudder_offset(&self) -> f32 {
+        f32::from_bits(self.0.rudder_offset.load(Relaxed))
+    }
+
     #[inline]
     pub async fn store_raw_target(&self, p: Physical) {
         self.0
@@ -50,11 +106,23 @@ impl Chassis {
             .store(unsafe { *(&p as *const _ as *const _) }, Relaxed);
     }
 
+    /// 控制源给出的原始目标，不含避障和运动约束的修正
+    ///
+    /// 底盘目标已过期或被释放（停止、急停、紧急制动）时视为释放
+    #[inline]
+    pub async fn commanded(&self) -> Physical {
+        let (time, p) = *self.0.target.lock().await;
+        if time.elapsed() < TARGET_TIMEOUT && !p.is_released() {
+            self.raw_target()
+        } else {
+            Physical::RELEASED
+        }
+    }
+
     #[inline]
     pub async fn predict(&self) -> Option<Trajectory> {
         self.0.predictor.lock().await.clone().map(|mut pre| {
-            pre.predictor.target =
-                unsafe { *(&self.0.raw_target.load(Relaxed) as *const _ as *const _) };
+            pre.predictor.target = self.raw_target();
             pre
         })
     }
@@ -63,8 +131,11 @@ impl Chassis {
         let (event, to_extern) = unbounded();
         let now = Instant::now();
         let chassis_clone = Self(Arc::new(Inner {
+            emergency_stop: AtomicBool::new(false),
             raw_target: AtomicU64::new(unsafe { *(&Physical::RELEASED as *const _ as *const _) }),
+            rudder_offset: AtomicU32::new(0f32.to_bits()),
             target: Mutex::new((now, Physical::RELEASED)),
+            limiter: Mutex::new(Limiter::new(Default::default())),
             model: Default::default(),
             predictor: Default::default(),
         }));
@@ -95,7 +166,7 @@ impl Chassis {
                         task::block_on(task::sleep(Duration::from_secs(1)));
                     }
                     Event(driver, e) => {
-                        driver.set_target(*task::block_on(chassis.0.target.lock()));
+                        driver.set_target(task::block_on(chassis.limited_target()));
                         if let Some(m) = task::block_on(chassis.0.model.lock()).take() {
                             driver.model = m
                         }
@@ -122,6 +193,31 @@ impl Chassis {
         (chassis_clone, to_extern)
     }
 
+    #[inline]
+    fn raw_target(&self) -> Physical {
+        unsafe { *(&self.0.raw_target.load(Relaxed) as *const _ as *const _) }
+    }
+
+    /// 经过运动约束的目标
+    ///
+    /// 过期的目标先视为释放再经过运动约束，按减速度刹停；
+    /// 输出的时间戳为当前时刻，避免驱动再次判定过期而跳过减速
+    #[inline]
+    async fn limited_target(&self) -> (Instant, Physical) {
+        let now = Instant::now();
+        let (time, target) = *self.0.target.lock().await;
+        let target = if now.saturating_duration_since(time) < TARGET_TIMEOUT {
+            target
+        } else {
+            Physical::RELEASED
+        };
+        let mut p = self.0.limiter.lock().await.next(target);
+        if !p.is_released() {
+            p.rudder = (p.rudder + self.rudder_offset()).clamp(-FRAC_PI_2, FRAC_PI_2);
+        }
+        (now, p)
+    }
+
     #[inline]
     async fn set_predictor(&self, driver: &PM1) {
         *self.0.predictor.lock().await = Some(Box::new(driver.trajectory_predictor()));
@@ -1,48 +1,177 @@
-﻿use async_std::sync::{Arc, Mutex};
+﻿use super::{send_async, Event};
+use async_std::{
+    channel::Sender,
+    sync::{Arc, Mutex},
+};
 use std::time::{Duration, Instant};
 
 const JOYSTICK_TIMEOUT: Duration = Duration::from_millis(500); // 手柄控制保护期
 const ARTIFICIAL_TIMEOUT: Duration = Duration::from_millis(500); // 人工控制保护期
+const AUTOMATIC_TIMEOUT: Duration = Duration::from_millis(500); // 自动控制保护期
+
+/// 已注册的控制源
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ControlSource(usize);
+
+/// 控制源无效
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ControlSourceError {
+    /// 控制源未注册或已注销
+    Unregistered,
+    /// 内置控制源不能注销
+    Builtin,
+}
+
+pub(super) const JOYSTICK: ControlSource = ControlSource(0);
+pub(super) const ARTIFICIAL: ControlSource = ControlSource(1);
+pub(super) const AUTOMATIC: ControlSource = ControlSource(2);
 
 /// 处理各种控制方式的优先级
+///
+/// 控制源在租期内阻止所有优先级不高于它的其他控制源，自动控制只让出、不阻止
 #[derive(Clone)]
 pub(super) struct DriveBlocking {
-    artificial_deadline: Arc<Mutex<Instant>>,
-    joystick_deadline: Arc<Mutex<Instant>>,
+    inner: Arc<Mutex<Inner>>,
+    event: Sender<Event>,
+}
+
+struct Inner {
+    /// 注销的控制源留空，避免旧句柄指向新的控制源
+    sources: Vec<Option<Source>>,
+    current: Option<usize>,
+}
+
+struct Source {
+    name: String,
+    priority: u32,
+    lease: Duration,
+    deadline: Instant,
+    /// 租期内是否阻止其他控制源
+    blocking: bool,
+}
+
+impl Inner {
+    /// 租期内优先级最高的控制源
+    fn holder(&self, now: Instant) -> Option<usize> {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().map(|s| (i, s)))
+            .filter(|(_, s)| now < s.deadline)
+            .max_by_key(|(_, s)| s.priority)
+            .map(|(i, _)| i)
+    }
+
+    #[inline]
+    fn name(&self, i: usize) -> Option<String> {
+        self.sources[i].as_ref().map(|s| s.name.clone())
+    }
+
+    /// 更新当前控制源，移交时返回新控制源的名字
+    fn handoff(&mut self, now: Instant) -> Option<Option<String>> {
+        let holder = self.holder(now);
+        if holder == self.current {
+            None
+        } else {
+            self.current = holder;
+            Some(holder.and_then(|i| self.name(i)))
+        }
+    }
 }
 
 impl DriveBlocking {
     #[inline]
-    pub fn new() -> Self {
+    pub fn new(event: Sender<Event>) -> Self {
         let now = Instant::now();
+        let source = |name: &str, priority, lease, blocking| {
+            Some(Source {
+                name: name.into(),
+                priority,
+                lease,
+                deadline: now,
+                blocking,
+            })
+        };
         Self {
-            artificial_deadline: Arc::new(Mutex::new(now)),
-            joystick_deadline: Arc::new(Mutex::new(now)),
+            inner: Arc::new(Mutex::new(Inner {
+                sources: vec![
+                    source("joystick", 200, JOYSTICK_TIMEOUT, true),
+                    source("artificial", 100, ARTIFICIAL_TIMEOUT, true),
+                    source("automatic", 0, AUTOMATIC_TIMEOUT, false),
+                ],
+                current: None,
+            })),
+            event,
         }
     }
 
-    #[inline]
-    pub async fn drive_joystick(&self) {
-        let now = Instant::now();
-        *self.joystick_deadline.lock().await = now + JOYSTICK_TIMEOUT;
+    /// 注册新的控制源
+    pub async fn register(&self, name: String, priority: u32, lease: Duration) -> ControlSource {
+        let mut inner = self.inner.lock().await;
+        inner.sources.push(Some(Source {
+            name,
+            priority,
+            lease,
+            deadline: Instant::now(),
+            blocking: true,
+        }));
+        ControlSource(inner.sources.len() - 1)
     }
 
-    #[inline]
-    pub async fn try_drive_automatic(&self) -> bool {
-        let now = Instant::now();
-        now > std::cmp::max(
-            *self.joystick_deadline.lock().await,
-            *self.artificial_deadline.lock().await,
-        )
+    /// 注销控制源，其租期立即失效
+    pub async fn unregister(&self, source: ControlSource) -> Result<(), ControlSourceError> {
+        if source.0 <= AUTOMATIC.0 {
+            return Err(ControlSourceError::Builtin);
+        }
+        let mut inner = self.inner.lock().await;
+        match inner.sources.get_mut(source.0) {
+            Some(s @ Some(_)) => *s = None,
+            _ => return Err(ControlSourceError::Unregistered),
+        }
+        let handoff = inner.handoff(Instant::now());
+        std::mem::drop(inner);
+        if let Some(name) = handoff {
+            send_async!(Event::ControlSourceChanged(name) => self.event).await;
+        }
+        Ok(())
     }
 
-    #[inline]
-    pub async fn try_drive_artificial(&self) -> bool {
+    /// 尝试以指定控制源控制，成功则续租
+    pub async fn try_drive(&self, source: ControlSource) -> Result<bool, ControlSourceError> {
         let now = Instant::now();
-        if now < *self.joystick_deadline.lock().await {
-            return false;
+        let mut inner = self.inner.lock().await;
+        let priority = match inner.sources.get(source.0) {
+            Some(Some(s)) => s.priority,
+            _ => return Err(ControlSourceError::Unregistered),
+        };
+        let blocked = inner.sources.iter().enumerate().any(|(i, s)| match s {
+            Some(s) => i != source.0 && s.blocking && now < s.deadline && s.priority >= priority,
+            None => false,
+        });
+        if !blocked {
+            if let Some(s) = &mut inner.sources[source.0] {
+                s.deadline = now + s.lease;
+            }
+        }
+        let handoff = inner.handoff(now);
+        std::mem::drop(inner);
+        if let Some(name) = handoff {
+            send_async!(Event::ControlSourceChanged(name) => self.event).await;
         }
-        *self.artificial_deadline.lock().await = now + ARTIFICIAL_TIMEOUT;
-        true
+        Ok(!blocked)
+    }
+
+    /// 检查租期是否到期
+    pub async fn check(&self) {
+        let handoff = self.inner.lock().await.handoff(Instant::now());
+        if let Some(name) = handoff {
+            send_async!(Event::ControlSourceChanged(name) => self.event).await;
+        }
+    }
+
+    /// 当前控制源的名字
+    pub async fn current(&self) -> Option<String> {
+        let inner = self.inner.lock().await;
+        inner.holder(Instant::now()).and_then(|i| inner.name(i))
     }
 }
@@ -1,4 +1,9 @@
-﻿macro_rules! particle_filter {
+﻿use parry2d::na::Matrix2;
+use pm1_sdk::model::Pm1Model;
+use pose_filter::Particle;
+use std::collections::HashSet;
+
+macro_rules! particle_filter {
     () => {
         Arc::new(Mutex::new(ParticleFilter::new(
             ParticleFilterParameters {
@@ -33,3 +38,151 @@ macro_rules! update_wheel {
         }
     }};
 }
+
+macro_rules! scatter {
+    ($filter:expr, $pose:expr, $sigma:expr) => {{
+        let sigma: f32 = $sigma;
+        scatter!($filter, $pose, sigma, sigma)
+    }};
+    ($filter:expr, $pose:expr, $sigma:expr, $heading:expr) => {{
+        let pose: Isometry2<f32> = $pose;
+        let sigma: f32 = $sigma;
+        let heading: f32 = $heading;
+        for p in $filter.particles_mut() {
+            p.pose = pose
+                * Isometry2::new(
+                    vector(gaussian() * sigma, gaussian() * sigma),
+                    gaussian() * heading,
+                );
+            p.weight = 1.0;
+        }
+    }};
+}
+
+/// 粒子分布的统计量
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Statistics {
+    /// 位置协方差
+    pub covariance: Matrix2<f32>,
+    /// 方向的圆方差（rad²）
+    pub heading_variance: f32,
+    /// 有效粒子数
+    pub effective: f32,
+}
+
+impl Statistics {
+    /// 位置标准差
+    #[inline]
+    pub fn spread(&self) -> f32 {
+        self.covariance.trace().sqrt()
+    }
+}
+
+/// 计算粒子分布的统计量
+pub(super) fn statistics(particles: &[Particle<Pm1Model>]) -> Option<Statistics> {
+    let (sum, squared) = particles.iter().fold((0.0, 0.0), |(sum, squared), p| {
+        (sum + p.weight, squared + p.weight * p.weight)
+    });
+    if !sum.is_normal() {
+        return None;
+    }
+    let (mean, sin, cos) = particles.iter().fold(
+        (super::vector(0.0, 0.0), 0.0, 0.0),
+        |(mean, sin, cos), p| {
+            let w = p.weight / sum;
+            let (s, c) = p.pose.rotation.angle().sin_cos();
+            (
+                mean + p.pose.translation.vector * w,
+                sin + s * w,
+                cos + c * w,
+            )
+        },
+    );
+    let covariance = particles.iter().fold(Matrix2::zeros(), |cov, p| {
+        let d = p.pose.translation.vector - mean;
+        cov + d * d.transpose() * (p.weight / sum)
+    });
+    let r = f32::hypot(sin, cos).clamp(f32::MIN_POSITIVE, 1.0);
+    Some(Statistics {
+        covariance,
+        heading_variance: -2.0 * r.ln(),
+        effective: sum * sum / squared,
+    })
+}
+
+/// 按似然为粒子加权并归一化，使平均权重为 1
+///
+/// 似然全部为零或数值溢出时不更新，返回是否已更新
+pub(super) fn reweight(
+    particles: &mut [Particle<Pm1Model>],
+    likelihood: impl Fn(&Particle<Pm1Model>) -> f32,
+) -> bool {
+    let weights = particles
+        .iter()
+        .map(|p| p.weight * likelihood(p))
+        .collect::<Vec<_>>();
+    let sum = weights.iter().sum::<f32>();
+    if !sum.is_normal() {
+        return false;
+    }
+    let k = particles.len() as f32 / sum;
+    for (p, w) in particles.iter_mut().zip(weights) {
+        p.weight = w * k;
+    }
+    true
+}
+
+/// KLD 自适应采样参数
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    /// 最少粒子数
+    pub min: usize,
+    /// 最多粒子数
+    pub max: usize,
+    /// 位置直方图的格子边长（m）
+    pub bin: f32,
+    /// 方向直方图的格子大小（rad）
+    pub angle_bin: f32,
+    /// 允许的 KL 散度误差
+    pub epsilon: f32,
+    /// 置信度对应的标准正态分位数
+    pub z: f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            min: 40,
+            max: 400,
+            bin: 0.2,
+            angle_bin: 0.2,
+            epsilon: 0.05,
+            z: 2.33, // 99%
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /// 按粒子占据的直方图格子数计算所需粒子数
+    pub(super) fn count(&self, particles: &[Particle<Pm1Model>]) -> usize {
+        let k = particles
+            .iter()
+            .map(|p| {
+                let v = p.pose.translation.vector;
+                (
+                    (v[0] / self.bin).floor() as i32,
+                    (v[1] / self.bin).floor() as i32,
+                    (p.pose.rotation.angle() / self.angle_bin).floor() as i32,
+                )
+            })
+            .collect::<HashSet<_>>()
+            .len();
+        if k < 2 {
+            return self.min;
+        }
+        let k = (k - 1) as f32;
+        let a = 2.0 / (9.0 * k);
+        let n = k / (2.0 * self.epsilon) * (1.0 - a + a.sqrt() * self.z).powi(3);
+        (n.ceil() as usize).clamp(self.min, self.max)
+    }
+}
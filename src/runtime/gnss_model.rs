@@ -0,0 +1,158 @@
+﻿use rtk_qxwz::GpggaStatus;
+use std::time::{Duration, Instant};
+
+const KIDNAP_COUNT: usize = 5; // 连续超限次数
+const KIDNAP_DURATION: Duration = Duration::from_secs(3); // 超限持续时间
+
+/// GGA 语句中与定位质量有关的字段
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct GgaQuality {
+    /// 使用的卫星数
+    pub satellites: u8,
+    /// 水平精度因子
+    pub hdop: f32,
+    /// 差分数据龄期（s）
+    pub age: Option<f32>,
+}
+
+impl GgaQuality {
+    /// 从 GGA 原始语句解析
+    pub fn parse(line: &str) -> Self {
+        let fields = line.split(',').collect::<Vec<_>>();
+        let field = |i: usize| fields.get(i).map(|s| s.trim()).filter(|s| !s.is_empty());
+        Self {
+            satellites: field(7).and_then(|s| s.parse().ok()).unwrap_or(0),
+            hdop: field(8)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(f32::INFINITY),
+            age: field(13).and_then(|s| s.parse().ok()),
+        }
+    }
+}
+
+/// 拒绝 GNSS 观测的原因
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GnssRejection {
+    /// 卫星数不足
+    Satellites(u8),
+    /// 水平精度因子过大
+    Hdop(f32),
+    /// 新息超出门限，参数为新息与门限的比值
+    Innovation(f32),
+}
+
+/// GNSS 观测模型
+#[derive(Clone, Copy, Debug)]
+pub struct GnssModel {
+    /// 单点解、伪距差分、浮点解、固定解的基础标准差（m）
+    pub sigma: [f32; 4],
+    /// 标准差随 HDOP 的放大系数
+    pub hdop_gain: f32,
+    /// HDOP 上限
+    pub max_hdop: f32,
+    /// 卫星数下限
+    pub min_satellites: u8,
+    /// 差分龄期上限（s），超出后按单点解处理
+    pub max_age: f32,
+    /// 新息门限，以标准差的倍数计
+    pub gate: f32,
+}
+
+impl Default for GnssModel {
+    fn default() -> Self {
+        Self {
+            sigma: [0.32, 0.16, 0.08, 0.04],
+            hdop_gain: 0.5,
+            max_hdop: 5.0,
+            min_satellites: 5,
+            max_age: 10.0,
+            gate: 4.0,
+        }
+    }
+}
+
+impl GnssModel {
+    /// 计算观测标准差，不可用的解状态返回 `Ok(None)`
+    pub fn sigma(
+        &self,
+        status: GpggaStatus,
+        quality: &GgaQuality,
+    ) -> Result<Option<f32>, GnssRejection> {
+        let level = match status {
+            GpggaStatus::单点解 => 0,
+            GpggaStatus::伪距差分 => 1,
+            GpggaStatus::浮点解 => 2,
+            GpggaStatus::固定解 => 3,
+            GpggaStatus::无效解
+            | GpggaStatus::PPS
+            | GpggaStatus::航位推算
+            | GpggaStatus::用户输入
+            | GpggaStatus::PPP => return Ok(None),
+        };
+        if quality.satellites < self.min_satellites {
+            return Err(GnssRejection::Satellites(quality.satellites));
+        }
+        if quality.hdop > self.max_hdop {
+            return Err(GnssRejection::Hdop(quality.hdop));
+        }
+        // 差分数据过期
+        let level = match quality.age {
+            Some(age) if age > self.max_age => 0,
+            _ => level,
+        };
+        let hdop = f32::max(quality.hdop - 1.0, 0.0);
+        Ok(Some(self.sigma[level] * (1.0 + self.hdop_gain * hdop)))
+    }
+
+    /// 检查新息，`innovation` 为观测与预测之差，`spread` 为粒子的位置标准差
+    pub fn gate(&self, innovation: f32, sigma: f32, spread: f32) -> Result<(), GnssRejection> {
+        let limit = self.gate * (sigma * sigma + spread * spread).sqrt();
+        if innovation > limit {
+            Err(GnssRejection::Innovation(innovation / limit))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// 持续的新息超限，说明机器人被挪动或长时间失锁后位姿已经错误
+#[derive(Default)]
+pub(super) struct KidnapDetector {
+    count: usize,
+    since: Option<Instant>,
+}
+
+impl KidnapDetector {
+    /// 观测被新息门限拒绝，返回是否应该重新初始化
+    pub fn rejected(&mut self, t: Instant) -> bool {
+        self.count += 1;
+        let since = *self.since.get_or_insert(t);
+        if self.count >= KIDNAP_COUNT && t.saturating_duration_since(since) >= KIDNAP_DURATION {
+            self.accepted();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 观测被接受
+    #[inline]
+    pub fn accepted(&mut self) {
+        self.count = 0;
+        self.since = None;
+    }
+}
+
+#[test]
+fn test() {
+    let quality = GgaQuality::parse(
+        "$GPGGA,085525.00,3959.58,N,11619.62,E,4,18,0.8,51.2,M,-8.7,M,1.0,0000*5A",
+    );
+    assert_eq!(18, quality.satellites);
+    assert_eq!(0.8, quality.hdop);
+    assert_eq!(Some(1.0), quality.age);
+    let model = GnssModel::default();
+    assert_eq!(Ok(Some(0.04)), model.sigma(GpggaStatus::固定解, &quality));
+    assert_eq!(Ok(None), model.sigma(GpggaStatus::无效解, &quality));
+    assert!(model.gate(0.5, 0.04, 0.05).is_err());
+}
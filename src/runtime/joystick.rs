@@ -1,23 +1,203 @@
-﻿use pm1_sdk::model::Physical;
+﻿use gilrs::{Button, EventType, Gilrs};
+use pm1_sdk::model::Physical;
 use std::f32::consts::FRAC_PI_2;
 use steering::{Device, Steering};
 
-pub(super) struct Joystick(Device);
+pub(super) struct Joystick {
+    device: Device,
+    buttons: Option<Gilrs>,
+}
+
+/// 手柄响应曲线，将 [0, 1] 的输入映射到 [0, 1]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResponseCurve {
+    Linear,
+    Quadratic,
+    /// 指数曲线，参数越大中间越平缓
+    Exponential(f32),
+}
+
+/// 手柄响应配置
+///
+/// 默认配置与原有的映射一致：速度线性，转向平方，只是转角限制在 ±π/2 以内
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct JoystickProfile {
+    /// 速度响应曲线
+    pub speed_curve: ResponseCurve,
+    /// 转向响应曲线
+    pub rudder_curve: ResponseCurve,
+    /// 摇杆死区，死区内视为松开
+    pub deadzone: f32,
+    /// 每档最大速度（m/s）
+    pub speed_per_level: f32,
+    /// 转向增益
+    pub rudder_gain: f32,
+    /// 最大倒车速度（m/s）
+    pub max_reverse: f32,
+}
+
+impl Default for JoystickProfile {
+    fn default() -> Self {
+        Self {
+            speed_curve: ResponseCurve::Linear,
+            rudder_curve: ResponseCurve::Quadratic,
+            deadzone: 0.005,
+            speed_per_level: 0.2,
+            rudder_gain: 1.0,
+            max_reverse: f32::INFINITY,
+        }
+    }
+}
+
+impl JoystickProfile {
+    /// 将摇杆状态映射为控制目标，速度方向由档位决定
+    fn map(&self, level: f32, rho: f32, theta: f32) -> Physical {
+        if rho < self.deadzone {
+            return Physical::RELEASED;
+        }
+        let speed = self.speed_curve.apply(rho) * level * self.speed_per_level;
+        // 倒车只限制幅值
+        let speed = f32::max(speed, -self.max_reverse);
+        let rudder = theta.signum()
+            * self.rudder_curve.apply(theta.abs() / FRAC_PI_2)
+            * FRAC_PI_2
+            * self.rudder_gain;
+        Physical {
+            speed,
+            rudder: rudder.clamp(-FRAC_PI_2, FRAC_PI_2),
+        }
+    }
+}
+
+impl ResponseCurve {
+    fn apply(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match *self {
+            Self::Linear => x,
+            Self::Quadratic => x * x,
+            Self::Exponential(k) if k > 0.0 => (k * x).exp_m1() / k.exp_m1(),
+            Self::Exponential(_) => x,
+        }
+    }
+}
+
+/// 手柄按键（按 xbox360 布局命名）
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadButton {
+    A,
+    B,
+    X,
+    Y,
+    LeftBumper,
+    RightBumper,
+    Back,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// 可绑定到手柄按键的动作
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GamepadAction {
+    /// 开始或停止录制路径
+    ToggleRecording,
+    /// 开始或停止循迹
+    ToggleTracking,
+    EmergencyStop,
+    /// 切换到下一档循迹速度
+    CycleSpeedPreset,
+    /// 标记当前位置
+    MarkWaypoint,
+}
+
+impl GamepadButton {
+    fn from_gilrs(button: Button) -> Option<Self> {
+        match button {
+            Button::South => Some(Self::A),
+            Button::East => Some(Self::B),
+            Button::West => Some(Self::X),
+            Button::North => Some(Self::Y),
+            Button::LeftTrigger => Some(Self::LeftBumper),
+            Button::RightTrigger => Some(Self::RightBumper),
+            Button::Select => Some(Self::Back),
+            Button::Start => Some(Self::Start),
+            Button::DPadUp => Some(Self::DPadUp),
+            Button::DPadDown => Some(Self::DPadDown),
+            Button::DPadLeft => Some(Self::DPadLeft),
+            Button::DPadRight => Some(Self::DPadRight),
+            _ => None,
+        }
+    }
+}
+
+/// 默认按键绑定
+pub(super) fn default_bindings() -> Vec<(GamepadButton, GamepadAction)> {
+    use GamepadAction::*;
+    vec![
+        (GamepadButton::Back, ToggleRecording),
+        (GamepadButton::Start, ToggleTracking),
+        (GamepadButton::B, EmergencyStop),
+        (GamepadButton::Y, CycleSpeedPreset),
+        (GamepadButton::X, MarkWaypoint),
+    ]
+}
 
 impl Joystick {
     pub fn new() -> Self {
-        Self(Device::new())
+        Self {
+            device: Device::new(),
+            buttons: Gilrs::new().ok(),
+        }
+    }
+
+    pub fn get(&mut self, profile: &JoystickProfile) -> Physical {
+        let steering::Status { level, rho, theta } = self.device.status();
+        profile.map(level as f32, rho, theta)
     }
 
-    pub fn get(&mut self) -> Physical {
-        let steering::Status { level, rho, theta } = self.0.status();
-        if rho < 0.005 {
-            Physical::RELEASED
-        } else {
-            Physical {
-                speed: rho * (level as f32) / 5.0,
-                rudder: theta.signum() * (theta / FRAC_PI_2).powi(2) * FRAC_PI_2,
+    /// 取出上次调用以来按下的按键
+    pub fn pressed(&mut self) -> Vec<GamepadButton> {
+        let mut pressed = Vec::new();
+        if let Some(ref mut gilrs) = self.buttons {
+            while let Some(e) = gilrs.next_event() {
+                if let EventType::ButtonPressed(button, _) = e.event {
+                    pressed.extend(GamepadButton::from_gilrs(button));
+                }
             }
         }
+        pressed
+    }
+}
+
+#[test]
+fn test() {
+    use std::f32::consts::{FRAC_PI_4, PI};
+
+    let profile = JoystickProfile::default();
+    // 默认配置与原有映射一致
+    let baseline = |level: f32, rho: f32, theta: f32| Physical {
+        speed: rho * level / 5.0,
+        rudder: theta.signum() * (theta / FRAC_PI_2).powi(2) * FRAC_PI_2,
+    };
+    for (level, rho, theta) in [(1.0, 0.5, 0.3), (5.0, 1.0, -1.2), (3.0, 0.01, FRAC_PI_2)] {
+        let p = profile.map(level, rho, theta);
+        let b = baseline(level, rho, theta);
+        assert!((p.speed - b.speed).abs() < 1e-6 && (p.rudder - b.rudder).abs() < 1e-6);
     }
+    assert!(profile.map(5.0, 0.004, 0.3).is_released());
+    // 摇杆指向后半圆不改变速度方向，转角限制在 ±π/2
+    let p = profile.map(1.0, 1.0, 3.0 * FRAC_PI_4);
+    let b = baseline(1.0, 1.0, 3.0 * FRAC_PI_4);
+    assert!((p.speed - b.speed).abs() < 1e-6 && (p.rudder - FRAC_PI_2).abs() < 1e-6);
+    // 倒车限速只限制向后的幅值
+    let limited = JoystickProfile {
+        max_reverse: 0.1,
+        ..profile
+    };
+    assert!((limited.map(5.0, 1.0, PI).speed - 1.0).abs() < 1e-6);
+    assert!((limited.map(-5.0, 1.0, 0.0).speed + 0.1).abs() < 1e-6);
+    assert!((limited.map(-5.0, 1.0, PI).speed + 0.1).abs() < 1e-6);
+    assert!((limited.map(-0.25, 1.0, 0.0).speed + 0.05).abs() < 1e-6);
 }
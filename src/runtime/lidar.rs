@@ -1,25 +1,27 @@
 ﻿use super::{send_async, CollisionInfo, Pose, Trajectory};
-use crate::Point;
 use async_std::{
     channel::{unbounded, Receiver},
+    sync::Arc,
     task,
 };
+use parry2d::na::Point2;
 use std::{
-    f32::consts::PI,
+    sync::atomic::{AtomicU8, Ordering::Relaxed},
     time::{Duration, Instant},
 };
 
 mod group;
 
-use group::Group;
-use lidar_ld19::{
-    driver::{Indexer, SupervisorEventForMultiple::*, SupervisorForMultiple},
-    CONFIG,
-};
+use group::{Body, Group};
+use lidar_ld19::driver::{Indexer, SupervisorEventForMultiple::*, SupervisorForMultiple};
 type LD19 = lidar_ld19::Lidar<lidar_ld19::LD19>;
 
 #[derive(Clone)]
-pub(super) struct Lidar(Group);
+pub(super) struct Lidar {
+    group: Group,
+    body: Body,
+    blind: Arc<AtomicU8>,
+}
 
 pub(super) enum Event {
     Connected,
@@ -27,28 +29,67 @@ pub(super) enum Event {
     FrameEncoded(Vec<u8>),
 }
 
-pub(super) const FILTERS: [fn(Point) -> bool; 2] = [
-    |Point { len: _, dir }| {
-        const DEG180: u16 = CONFIG.dir_round / 2;
-        const DEG90: u16 = DEG180 / 2;
-        const DEG30: u16 = DEG90 / 3;
-        (DEG90 < dir && dir <= DEG180 - DEG30) || (DEG180 + DEG30 < dir && dir <= (DEG180 + DEG90))
-    },
-    |Point { len: _, dir }| {
-        const LIMIT: u16 = (CONFIG.dir_round as f32 * 1.5 / (2.0 * PI)) as u16;
-        dir < LIMIT || (CONFIG.dir_round - LIMIT) <= dir
-    },
-];
+pub(super) const REAR: usize = 0;
+pub(super) const FRONT: usize = 1;
+
+pub(super) const STALE_TIMEOUT: Duration = Duration::from_millis(500); // 雷达数据过期时间
 
 impl Lidar {
     #[inline]
     pub async fn check(&self, trajectory: Trajectory) -> Option<CollisionInfo> {
-        self.0.detect(trajectory).await
+        self.group.detect(trajectory).await
+    }
+
+    /// 合并的点云，机器人坐标系，以及最近的更新时间
+    #[inline]
+    pub async fn snapshot(&self) -> (Option<Instant>, Vec<Point2<f32>>) {
+        self.group.snapshot().await
+    }
+
+    /// 各雷达的安装位置和点云，机器人坐标系
+    #[inline]
+    pub async fn scans(&self) -> Vec<(Point2<f32>, Vec<Point2<f32>>)> {
+        self.group.scans().await
+    }
+
+    /// 设置负载轮廓，落在轮廓内的点不作为障碍物
+    #[inline]
+    pub async fn set_payload(&self, shapes: &[Vec<(f32, f32)>]) {
+        self.body.set_payload(shapes).await;
+    }
+
+    /// 雷达是否失明（数据过期或没有数据）
+    #[inline]
+    pub fn is_blind(&self, i: usize) -> bool {
+        self.blind.load(Relaxed) & (1 << i) != 0
+    }
+
+    #[inline]
+    pub fn any_blind(&self) -> bool {
+        self.blind.load(Relaxed) != 0
+    }
+
+    /// 根据数据时龄更新失明状态，返回状态改变的雷达
+    pub async fn update_blind(&self) -> Vec<(usize, bool)> {
+        let mask = self
+            .group
+            .ages()
+            .await
+            .into_iter()
+            .enumerate()
+            .filter(|(_, age)| age.map_or(true, |age| age > STALE_TIMEOUT))
+            .fold(0u8, |mask, (i, _)| mask | (1 << i));
+        let last = self.blind.swap(mask, Relaxed);
+        (0..8)
+            .filter(|i| (last ^ mask) & (1 << i) != 0)
+            .map(|i| (i, mask & (1 << i) != 0))
+            .collect()
     }
 
     pub fn supervisor() -> (Self, Receiver<Event>) {
         let (event, to_extern) = unbounded();
-        let (group, mut collectors) = Group::build(&[
+        let body = Body::new();
+        let trans = [
             Pose {
                 x: -0.141,
                 y: 0.0,
@@ -59,7 +100,8 @@ impl Lidar {
                 y: 0.0,
                 theta: 0.0,
             },
-        ]);
+        ];
+        let (group, mut collectors) = Group::build(&trans, &body);
         task::spawn_blocking(move || {
             let mut indexer = Indexer::new(2);
             let mut send_time = Instant::now() + Duration::from_millis(100);
@@ -70,9 +112,9 @@ impl Lidar {
                         task::block_on(send_async!(Event::Connected => event));
                         // 为 LD19 设置置信度阈值
                         *driver.inner.min_confidence_mut() = 120;
+                        // 自身遮挡由轮廓过滤，雷达不再屏蔽角度
+                        driver.filter = |_| true;
                         if let Some(i) = indexer.add(k.clone()) {
-                            // 为雷达设置过滤器
-                            driver.filter = FILTERS[i];
                             // 挪动的雷达清除缓存
                             task::block_on(collectors[i].clear());
                         }
@@ -89,13 +131,12 @@ impl Lidar {
                         target: _,
                         next_try,
                     } => *next_try = Instant::now() + Duration::from_secs(1),
-                    Event(k, e, s) => {
+                    Event(k, e, _) => {
                         let now = Instant::now();
                         // 更新
                         if let Some(j) = indexer.find(&k) {
-                            // 挪动的雷达更新过滤器并清除缓存
+                            // 挪动的雷达清除缓存
                             if indexer.update(j) {
-                                let _ = s.send(FILTERS[j]);
                                 task::block_on(collectors[j].clear());
                             }
                             if let Some((_, (i, s))) = e {
@@ -115,6 +156,14 @@ impl Lidar {
                 2
             });
         });
-        (Self(group), to_extern)
+        (
+            Self {
+                group,
+                body,
+                // 收到数据前所有雷达视为失明
+                blind: Arc::new(AtomicU8::new((1 << trans.len()) - 1)),
+            },
+            to_extern,
+        )
     }
 }
@@ -1,6 +1,9 @@
-﻿use super::super::{CollisionInfo, Trajectory};
+﻿use super::{
+    super::{CollisionInfo, Trajectory},
+    STALE_TIMEOUT,
+};
 use crate::{vector, Point, Pose, CONFIG};
-use async_std::sync::{Arc, Mutex};
+use async_std::sync::{Arc, Mutex, RwLock};
 pub use lidar_ld19::zip;
 use parry2d::{
     math::{self, Real},
@@ -9,17 +12,24 @@ use parry2d::{
     shape::ConvexPolygon,
 };
 use pm1_sdk::model::Odometry;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Clone)]
-pub(super) struct Group(Vec<Points>);
+pub(super) struct Group(Vec<Points>, Vec<Pose>);
 
 pub(super) struct Collector {
     points: Points,
     bits: Vec<Vec<u8>>,
     trans: Pose,
+    body: Body,
 }
 
+/// 机器人自身和负载的轮廓，落在其中的点是自身遮挡
+#[derive(Clone)]
+pub(super) struct Body(Arc<RwLock<Vec<ConvexPolygon>>>);
+
+const BODY_MARGIN: f32 = 0.03; // 自身轮廓外扩距离
+
 #[cfg(debug_assertions)]
 mod c {
     pub const DETECT_STEP_S: f32 = 0.1;
@@ -32,20 +42,67 @@ mod c {
     pub const DETECT_STEP_A: f32 = std::f32::consts::PI / 18.0;
 }
 
-type Points = Arc<Mutex<Vec<Vec<math::Point<Real>>>>>;
+type Points = Arc<Mutex<Cloud>>;
+
+/// 单个雷达的点云和更新时间
+#[derive(Default)]
+struct Cloud {
+    update_time: Option<Instant>,
+    sections: Vec<(Instant, Vec<math::Point<Real>>)>,
+}
+
+impl Body {
+    pub fn new() -> Self {
+        Self(Arc::new(RwLock::new(
+            vec![outline(&ROBOT_OUTLINE).unwrap()],
+        )))
+    }
+
+    /// 替换负载轮廓，机器人本体轮廓保留
+    pub async fn set_payload(&self, shapes: &[Vec<(f32, f32)>]) {
+        let mut polygons = vec![outline(&ROBOT_OUTLINE).unwrap()];
+        polygons.extend(shapes.iter().filter_map(|s| outline(s)));
+        *self.0.write().await = polygons;
+    }
+}
+
+/// 外扩并取凸包
+fn outline(shape: &[(f32, f32)]) -> Option<ConvexPolygon> {
+    let points = shape
+        .iter()
+        .map(|(x, y)| {
+            let v = vector(*x, *y);
+            let norm = v.norm();
+            math::Point {
+                coords: if norm > 0.0 {
+                    v * (1.0 + BODY_MARGIN / norm)
+                } else {
+                    v
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    ConvexPolygon::from_convex_hull(&points)
+}
 
 impl Collector {
     pub async fn put(&mut self, i: usize, section: Vec<Point>) {
-        // 变换
+        // 变换并滤除自身遮挡
+        let body = self.body.0.read().await;
         let mut zipped = Vec::with_capacity(section.len() * CONFIG.zipped_size);
         let mut transed = Vec::with_capacity(section.len());
         for p in section {
-            let _ = zipped.extend(zip(p));
             let (x, y) = self.trans.transform_point(p);
-            transed.push(math::Point {
+            let transformed = math::Point {
                 coords: vector(x, y),
-            });
+            };
+            if body.iter().any(|b| b.contains_local_point(&transformed)) {
+                continue;
+            }
+            let _ = zipped.extend(zip(p));
+            transed.push(transformed);
         }
+        std::mem::drop(body);
         // 保存编码
         let bits = &mut self.bits;
         if bits.len() <= i {
@@ -53,11 +110,13 @@ impl Collector {
         }
         bits[i] = zipped;
         // 保存点云
-        let points = &mut self.points.lock().await;
-        if points.len() <= i {
-            points.resize_with(i + 1, Vec::new);
+        let now = Instant::now();
+        let cloud = &mut self.points.lock().await;
+        if cloud.sections.len() <= i {
+            cloud.sections.resize_with(i + 1, || (now, Vec::new()));
         }
-        points[i] = transed;
+        cloud.sections[i] = (now, transed);
+        cloud.update_time = Some(now);
     }
 
     /// 将全部编码写入到缓冲区
@@ -71,27 +130,67 @@ impl Collector {
 
     /// 清空
     pub async fn clear(&mut self) {
-        self.points.lock().await.clear();
+        *self.points.lock().await = Default::default();
         self.bits.clear();
     }
 }
 
 impl Group {
-    pub fn build(trans: &[Pose]) -> (Self, Vec<Collector>) {
+    pub fn build(trans: &[Pose], body: &Body) -> (Self, Vec<Collector>) {
         let collectors = trans
             .iter()
             .map(|trans| Collector {
-                points: Arc::new(Mutex::new(Vec::new())),
+                points: Default::default(),
                 bits: Vec::new(),
                 trans: *trans,
+                body: body.clone(),
             })
             .collect::<Vec<_>>();
         (
-            Self(collectors.iter().map(|c| c.points.clone()).collect()),
+            Self(
+                collectors.iter().map(|c| c.points.clone()).collect(),
+                trans.to_vec(),
+            ),
             collectors,
         )
     }
 
+    /// 合并的点云，机器人坐标系，以及最近的更新时间
+    pub async fn snapshot(&self) -> (Option<Instant>, Vec<math::Point<Real>>) {
+        let mut time = None;
+        let mut points = Vec::new();
+        for x in &self.0 {
+            let cloud = x.lock().await;
+            time = time.max(cloud.update_time);
+            points.extend(cloud.points());
+        }
+        (time, points)
+    }
+
+    /// 各雷达的安装位置和点云，机器人坐标系
+    pub async fn scans(&self) -> Vec<(math::Point<Real>, Vec<math::Point<Real>>)> {
+        let mut scans = Vec::with_capacity(self.0.len());
+        for (x, trans) in self.0.iter().zip(&self.1) {
+            let points = x.lock().await.points().copied().collect();
+            scans.push((
+                math::Point {
+                    coords: vector(trans.x, trans.y),
+                },
+                points,
+            ));
+        }
+        scans
+    }
+
+    /// 各雷达数据的时龄，从未收到数据则为 `None`
+    pub async fn ages(&self) -> Vec<Option<Duration>> {
+        let mut ages = Vec::with_capacity(self.0.len());
+        for x in &self.0 {
+            ages.push(x.lock().await.update_time.map(|t| t.elapsed()));
+        }
+        ages
+    }
+
     pub async fn detect(&self, trajectory: Trajectory) -> Option<CollisionInfo> {
         // 锁定整个点云
         let mut frame = Vec::with_capacity(self.0.len());
@@ -129,10 +228,11 @@ impl Group {
             // 计算包装盒，降低检测复杂度
             let aabb = outline.local_aabb();
             // 遍历检测碰撞
-            if frame
+            if let Some((lidar, obstacle)) = frame
                 .iter()
-                .flat_map(|c| c.iter().flatten())
-                .any(|p| aabb.contains_local_point(p) && outline.contains_local_point(p))
+                .enumerate()
+                .flat_map(|(i, c)| c.fresh().map(move |p| (i, p)))
+                .find(|(_, p)| aabb.contains_local_point(p) && outline.contains_local_point(p))
             {
                 // 检测到碰撞
                 let risk = 1.0 / size;
@@ -144,7 +244,7 @@ impl Group {
                 if risk < 1.0 {
                     let (l, r) =
                     // 展平
-                    frame.iter().flat_map(|c| c.iter().flatten())
+                    frame.iter().flat_map(|c| c.fresh())
                     // 累加障碍点数和合力
                     .fold(
                         (Force::ZERO, Force::ZERO),
@@ -175,6 +275,8 @@ impl Group {
                     pose: odom,
                     risk,
                     force,
+                    obstacle: *obstacle,
+                    lidar,
                 });
             }
         }
@@ -182,6 +284,21 @@ impl Group {
     }
 }
 
+impl Cloud {
+    /// 全部点
+    fn points(&self) -> impl Iterator<Item = &math::Point<Real>> {
+        self.sections.iter().flat_map(|(_, s)| s)
+    }
+
+    /// 未过期的点，过期的数据不能作为没有障碍物的依据
+    fn fresh(&self) -> impl Iterator<Item = &math::Point<Real>> {
+        self.sections
+            .iter()
+            .filter(|(t, _)| t.elapsed() <= STALE_TIMEOUT)
+            .flat_map(|(_, s)| s)
+    }
+}
+
 struct Force {
     count: usize,
     value: Vector2<f32>,
@@ -0,0 +1,292 @@
+﻿use crate::LOCAL_ORIGIN;
+use async_std::{fs, io, path::Path};
+use parry2d::na::Point2;
+use std::collections::HashMap;
+
+pub(super) const MAP_IMAGE: &str = "map.pgm";
+pub(super) const MAP_META: &str = "map.yaml";
+
+const LOG_ODDS_HIT: f32 = 0.85;
+const LOG_ODDS_MISS: f32 = -0.4;
+const LOG_ODDS_LIMIT: f32 = 4.0;
+const MAX_RANGE: f32 = 12.0; // 超出量程的点不建图
+
+const FIELD_SIGMA: f32 = 0.1; // 似然场标准差
+const FIELD_MAX_DISTANCE: f32 = 0.5; // 似然场最大距离
+const FIELD_RANDOM: f32 = 0.05; // 随机测量的概率
+
+/// 全局占据栅格，坐标为本地 ENU
+pub(super) struct OccupancyGrid {
+    resolution: f32,
+    cells: HashMap<(i32, i32), f32>,
+}
+
+impl OccupancyGrid {
+    #[inline]
+    pub fn new(resolution: f32) -> Self {
+        Self {
+            resolution,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// 插入一个雷达的扫描，`origin` 为雷达位置，`hits` 为障碍物点
+    pub fn insert(&mut self, origin: Point2<f32>, hits: &[Point2<f32>]) {
+        let o = self.cell(origin);
+        for hit in hits {
+            if (hit - origin).norm() > MAX_RANGE {
+                continue;
+            }
+            let h = self.cell(*hit);
+            // 光线经过的栅格是空闲的
+            for c in line(o, h) {
+                if c != h {
+                    self.update(c, LOG_ODDS_MISS);
+                }
+            }
+            self.update(h, LOG_ODDS_HIT);
+        }
+    }
+
+    /// 保存为 PGM 图像和 yaml 元数据
+    pub async fn save(&self, dir: &Path) -> io::Result<()> {
+        let (x0, y0, x1, y1) = self.cells.keys().fold(
+            (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+            |(x0, y0, x1, y1), (x, y)| (x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y)),
+        );
+        if x0 > x1 {
+            return Err(io::Error::new(io::ErrorKind::Other, "empty map"));
+        }
+        let (width, height) = ((x1 - x0 + 1) as usize, (y1 - y0 + 1) as usize);
+        let mut image = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+        image.reserve(width * height);
+        // 图像首行对应最大的 y
+        for y in (y0..=y1).rev() {
+            for x in x0..=x1 {
+                image.push(match self.cells.get(&(x, y)) {
+                    Some(l) if *l > 0.0 => 0,
+                    Some(l) if *l < 0.0 => 254,
+                    _ => 205,
+                });
+            }
+        }
+        let meta = format!(
+            "image: {}\nresolution: {}\norigin: [{}, {}, 0.0]\nnegate: 0\noccupied_thresh: 0.65\nfree_thresh: 0.196\nreference: [{}, {}, {}]\n",
+            MAP_IMAGE,
+            self.resolution,
+            x0 as f32 * self.resolution,
+            y0 as f32 * self.resolution,
+            LOCAL_ORIGIN.latitude,
+            LOCAL_ORIGIN.longitude,
+            LOCAL_ORIGIN.altitude,
+        );
+        fs::write(dir.join(MAP_IMAGE), image).await?;
+        fs::write(dir.join(MAP_META), meta).await
+    }
+
+    #[inline]
+    fn cell(&self, p: Point2<f32>) -> (i32, i32) {
+        (
+            (p[0] / self.resolution).floor() as i32,
+            (p[1] / self.resolution).floor() as i32,
+        )
+    }
+
+    #[inline]
+    fn update(&mut self, c: (i32, i32), l: f32) {
+        let cell = self.cells.entry(c).or_insert(0.0);
+        *cell = (*cell + l).clamp(-LOG_ODDS_LIMIT, LOG_ODDS_LIMIT);
+    }
+}
+
+/// 由占据栅格地图计算的似然场
+pub(super) struct LikelihoodField {
+    resolution: f32,
+    origin: (f32, f32),
+    width: usize,
+    height: usize,
+    /// 到最近障碍物的距离，首行对应最小的 y
+    distance: Vec<f32>,
+}
+
+impl LikelihoodField {
+    /// 从目录加载地图
+    pub async fn load(dir: &Path) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        // 元数据
+        let meta = fs::read_to_string(dir.join(MAP_META)).await?;
+        let mut image = MAP_IMAGE.to_string();
+        let mut resolution = None;
+        let mut origin = None;
+        let mut occupied_thresh = 0.65;
+        for line in meta.lines() {
+            if let Some((key, value)) = line.split_once(':') {
+                let value = value.trim();
+                match key.trim() {
+                    "image" => image = value.to_string(),
+                    "resolution" => resolution = value.parse::<f32>().ok(),
+                    "occupied_thresh" => occupied_thresh = value.parse().unwrap_or(0.65),
+                    "origin" => {
+                        let v = value
+                            .trim_matches(|c| c == '[' || c == ']')
+                            .split(',')
+                            .filter_map(|x| x.trim().parse::<f32>().ok())
+                            .collect::<Vec<_>>();
+                        if v.len() >= 2 {
+                            origin = Some((v[0], v[1]));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let resolution = resolution.ok_or_else(|| invalid("missing resolution"))?;
+        let origin = origin.ok_or_else(|| invalid("missing origin"))?;
+        // 图像
+        let bytes = fs::read(dir.join(image)).await?;
+        let (width, height, max, data) = parse_pgm(&bytes).ok_or_else(|| invalid("invalid pgm"))?;
+        // 翻转行序，计算距离
+        let mut distance = vec![FIELD_MAX_DISTANCE; width * height];
+        for y in 0..height {
+            let row = &data[(height - 1 - y) * width..][..width];
+            for (x, v) in row.iter().enumerate() {
+                if (max - *v as f32) / max > occupied_thresh {
+                    distance[y * width + x] = 0.0;
+                }
+            }
+        }
+        chamfer(&mut distance, width, height, resolution);
+        Ok(Self {
+            resolution,
+            origin,
+            width,
+            height,
+            distance,
+        })
+    }
+
+    /// 点集的平均似然，点在本地 ENU 坐标系
+    pub fn likelihood(&self, points: impl Iterator<Item = Point2<f32>>) -> f32 {
+        let (sum, n) = points.fold((0.0, 0), |(sum, n), p| {
+            let d = self.distance(p);
+            let l = (-d * d / (2.0 * FIELD_SIGMA * FIELD_SIGMA)).exp();
+            (sum + (1.0 - FIELD_RANDOM) * l + FIELD_RANDOM, n + 1)
+        });
+        if n == 0 {
+            1.0
+        } else {
+            sum / n as f32
+        }
+    }
+
+    fn distance(&self, p: Point2<f32>) -> f32 {
+        let x = ((p[0] - self.origin.0) / self.resolution).floor();
+        let y = ((p[1] - self.origin.1) / self.resolution).floor();
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            FIELD_MAX_DISTANCE
+        } else {
+            self.distance[y as usize * self.width + x as usize]
+        }
+    }
+}
+
+/// 解析 P5 格式的 PGM 图像，返回宽、高、最大值和像素
+fn parse_pgm(bytes: &[u8]) -> Option<(usize, usize, f32, &[u8])> {
+    let mut fields = Vec::with_capacity(4);
+    let mut i = 0;
+    while fields.len() < 4 {
+        match bytes.get(i)? {
+            b'#' => {
+                while *bytes.get(i)? != b'\n' {
+                    i += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            _ => {
+                let begin = i;
+                while !bytes.get(i)?.is_ascii_whitespace() {
+                    i += 1;
+                }
+                fields.push(std::str::from_utf8(&bytes[begin..i]).ok()?);
+            }
+        }
+    }
+    if fields[0] != "P5" {
+        return None;
+    }
+    let width = fields[1].parse().ok()?;
+    let height = fields[2].parse().ok()?;
+    let max = fields[3].parse::<u16>().ok()?;
+    if max > 255 {
+        return None;
+    }
+    // 头部之后跟一个空白字符
+    let data = bytes.get(i + 1..i + 1 + width * height)?;
+    Some((width, height, max as f32, data))
+}
+
+/// 两遍倒角距离变换
+fn chamfer(d: &mut [f32], width: usize, height: usize, resolution: f32) {
+    let (a, b) = (resolution, resolution * std::f32::consts::SQRT_2);
+    for y in 0..height {
+        for x in 0..width {
+            let mut v = d[y * width + x];
+            if x > 0 {
+                v = v.min(d[y * width + x - 1] + a);
+            }
+            if y > 0 {
+                v = v.min(d[(y - 1) * width + x] + a);
+                if x > 0 {
+                    v = v.min(d[(y - 1) * width + x - 1] + b);
+                }
+                if x + 1 < width {
+                    v = v.min(d[(y - 1) * width + x + 1] + b);
+                }
+            }
+            d[y * width + x] = v;
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            let mut v = d[y * width + x];
+            if x + 1 < width {
+                v = v.min(d[y * width + x + 1] + a);
+            }
+            if y + 1 < height {
+                v = v.min(d[(y + 1) * width + x] + a);
+                if x + 1 < width {
+                    v = v.min(d[(y + 1) * width + x + 1] + b);
+                }
+                if x > 0 {
+                    v = v.min(d[(y + 1) * width + x - 1] + b);
+                }
+            }
+            d[y * width + x] = v;
+        }
+    }
+}
+
+/// Bresenham 直线经过的栅格
+fn line((x0, y0): (i32, i32), (x1, y1): (i32, i32)) -> Vec<(i32, i32)> {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let mut err = dx + dy;
+    let (mut x, mut y) = (x0, y0);
+    let mut cells = Vec::with_capacity((dx - dy) as usize + 1);
+    loop {
+        cells.push((x, y));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    cells
+}
@@ -0,0 +1,94 @@
+﻿use async_std::{fs, io, path::Path};
+use parry2d::na::Isometry2;
+use pm1_sdk::model::Pm1Model;
+use std::fmt;
+
+pub(super) const STATE_FILE: &str = "localization"; // 定位状态保存文件
+
+/// 跨重启保存的定位状态
+#[derive(Clone, Default)]
+pub(super) struct SavedState {
+    /// 标定后的底盘模型
+    pub model: Option<Pm1Model>,
+    /// 舵轮零偏
+    pub rudder_offset: Option<f32>,
+    /// 最后一次收敛的滤波器位姿，及其位置（m）和方向（rad）的标准差
+    pub pose: Option<(Isometry2<f32>, f32, f32)>,
+    /// 航位推算的位姿，与滤波器位姿不在同一坐标系
+    pub odometry: Option<Isometry2<f32>>,
+}
+
+impl SavedState {
+    /// 读取状态，文件不存在或损坏时返回空状态
+    pub async fn load(path: &Path) -> Self {
+        match fs::read_to_string(path).await {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// 先写临时文件再替换，避免断电时留下半个文件
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, self.to_string()).await?;
+        fs::rename(temp, path).await
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut state = Self::default();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let key = words.next();
+            let values = words
+                .filter_map(|w| w.parse::<f32>().ok())
+                .collect::<Vec<_>>();
+            match (key, values.as_slice()) {
+                (Some("model"), [width, length, wheel]) if wheel.is_normal() => {
+                    state.model = Some(Pm1Model::new(*width, *length, *wheel));
+                }
+                (Some("rudder"), [offset]) => state.rudder_offset = Some(*offset),
+                (Some("pose"), [x, y, theta, sigma, heading]) => {
+                    let pose = Isometry2::new([*x, *y].into(), *theta);
+                    state.pose = Some((pose, *sigma, *heading));
+                }
+                (Some("odometry"), [x, y, theta]) => {
+                    state.odometry = Some(Isometry2::new([*x, *y].into(), *theta));
+                }
+                _ => {}
+            }
+        }
+        state
+    }
+}
+
+impl fmt::Display for SavedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref model) = self.model {
+            writeln!(f, "model {} {} {}", model.width, model.length, model.wheel)?;
+        }
+        if let Some(offset) = self.rudder_offset {
+            writeln!(f, "rudder {}", offset)?;
+        }
+        if let Some((pose, sigma, heading)) = self.pose {
+            writeln!(
+                f,
+                "pose {} {} {} {} {}",
+                pose.translation.vector[0],
+                pose.translation.vector[1],
+                pose.rotation.angle(),
+                sigma,
+                heading
+            )?;
+        }
+        if let Some(pose) = self.odometry {
+            writeln!(
+                f,
+                "odometry {} {} {}",
+                pose.translation.vector[0],
+                pose.translation.vector[1],
+                pose.rotation.angle()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+﻿use pm1_sdk::model::Physical;
+use std::{f32::consts::PI, time::Instant};
+
+/// 运动约束
+#[derive(Clone, Copy, Debug)]
+pub struct MotionProfile {
+    /// 最大速度（m/s）
+    pub max_speed: f32,
+    /// 最大加速度（m/s²）
+    pub acceleration: f32,
+    /// 最大减速度（m/s²）
+    pub deceleration: f32,
+    /// 后轮最大转速（rad/s）
+    pub rudder_rate: f32,
+}
+
+impl Default for MotionProfile {
+    fn default() -> Self {
+        Self {
+            max_speed: 1.5,
+            acceleration: 0.8,
+            deceleration: 1.6,
+            rudder_rate: PI,
+        }
+    }
+}
+
+impl MotionProfile {
+    /// 从上一个输出 `last` 向目标 `target` 过渡 `dt` 秒
+    pub fn limit(&self, last: Physical, target: Physical, dt: f32) -> Physical {
+        let last_speed = if last.is_released() { 0.0 } else { last.speed };
+        // 释放：按减速度刹停，保持转角
+        if target.is_released() {
+            let speed = approach(last_speed, 0.0, self.deceleration * dt);
+            return if speed == 0.0 {
+                Physical::RELEASED
+            } else {
+                Physical {
+                    speed,
+                    rudder: last.rudder,
+                }
+            };
+        }
+        let target_speed = target.speed.clamp(-self.max_speed, self.max_speed);
+        let speed = if last_speed * target_speed < 0.0 {
+            // 换向必须先减速到零
+            approach(last_speed, 0.0, self.deceleration * dt)
+        } else if target_speed.abs() > last_speed.abs() {
+            approach(last_speed, target_speed, self.acceleration * dt)
+        } else {
+            approach(last_speed, target_speed, self.deceleration * dt)
+        };
+        let rudder = if last.is_released() {
+            target.rudder
+        } else {
+            approach(last.rudder, target.rudder, self.rudder_rate * dt)
+        };
+        Physical { speed, rudder }
+    }
+}
+
+/// 控制量限幅器，位于底盘目标之前
+pub(super) struct Limiter {
+    pub profile: MotionProfile,
+    time: Instant,
+    output: Physical,
+}
+
+impl Limiter {
+    #[inline]
+    pub fn new(profile: MotionProfile) -> Self {
+        Self {
+            profile,
+            time: Instant::now(),
+            output: Physical::RELEASED,
+        }
+    }
+
+    /// 计算当前时刻的受限输出
+    pub fn next(&mut self, target: Physical) -> Physical {
+        let now = Instant::now();
+        let dt = now.saturating_duration_since(self.time).as_secs_f32();
+        self.time = now;
+        self.output = self.profile.limit(self.output, target, dt);
+        self.output
+    }
+
+    /// 紧急制动，绕过所有约束
+    #[inline]
+    pub fn brake(&mut self) {
+        self.time = Instant::now();
+        self.output = Physical::RELEASED;
+    }
+}
+
+#[inline]
+fn approach(from: f32, to: f32, step: f32) -> f32 {
+    if from < to {
+        f32::min(from + step, to)
+    } else {
+        f32::max(from - step, to)
+    }
+}
+
+#[test]
+fn test() {
+    let profile = MotionProfile::default();
+    let p = profile.limit(
+        Physical::RELEASED,
+        Physical {
+            speed: 1.0,
+            rudder: 0.0,
+        },
+        0.5,
+    );
+    assert_eq!(0.4, p.speed);
+    let p = profile.limit(
+        p,
+        Physical {
+            speed: -1.0,
+            rudder: 0.0,
+        },
+        0.1,
+    );
+    assert!((p.speed - 0.24).abs() < 1e-6);
+    assert!(profile.limit(p, Physical::RELEASED, 1.0).is_released());
+}
@@ -0,0 +1,347 @@
+﻿use super::filter::Statistics;
+use parry2d::na::Matrix2;
+use std::time::{Duration, Instant};
+
+const CONVERGED_SPREAD: f32 = 0.3; // 收敛的位置标准差（m）
+const CONVERGED_HEADING: f32 = 0.1; // 收敛的方向方差（rad²）
+const DIVERGED_SPREAD: f32 = 1.0; // 发散的位置标准差（m）
+const DIVERGED_HEADING: f32 = 0.5; // 发散的方向方差（rad²）
+const CONVERGED_COUNT: usize = 3; // 连续满足条件的次数
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_secs(10); // 超过此时间没有外部观测视为仅靠轮速计
+
+/// 最近一次使用的观测来源
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MeasurementSource {
+    /// 仅靠轮速计
+    Odometry,
+    Gnss,
+    ScanMatching,
+    Map,
+    /// 手动设置位姿
+    Manual,
+}
+
+/// 定位收敛状态
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Convergence {
+    /// 尚未收敛
+    Converging,
+    Converged,
+    /// 曾经收敛，现已发散
+    Diverged,
+}
+
+/// 定位质量
+#[derive(Clone, Copy, Debug)]
+pub struct LocalizationQuality {
+    /// 粒子位置协方差（m²）
+    pub covariance: Matrix2<f32>,
+    /// 粒子方向方差（rad²）
+    pub heading_variance: f32,
+    /// 有效粒子数
+    pub effective: f32,
+    /// 粒子数
+    pub count: usize,
+    /// 自适应采样的目标粒子数
+    pub target_count: usize,
+    pub source: MeasurementSource,
+    /// 距上次 GNSS 观测的时间
+    pub since_gnss: Option<Duration>,
+    pub convergence: Convergence,
+}
+
+impl LocalizationQuality {
+    /// 位置标准差
+    #[inline]
+    pub fn spread(&self) -> f32 {
+        self.covariance.trace().sqrt()
+    }
+
+    /// 位姿是否可信
+    #[inline]
+    pub fn is_trustworthy(&self) -> bool {
+        self.convergence == Convergence::Converged
+    }
+}
+
+/// 跟踪观测来源和收敛状态
+pub(super) struct QualityMonitor {
+    last: Option<(MeasurementSource, Instant)>,
+    last_gnss: Option<Instant>,
+    convergence: Convergence,
+    count: usize,
+    quality: Option<LocalizationQuality>,
+}
+
+impl Default for QualityMonitor {
+    fn default() -> Self {
+        Self {
+            last: None,
+            last_gnss: None,
+            convergence: Convergence::Converging,
+            count: 0,
+            quality: None,
+        }
+    }
+}
+
+impl QualityMonitor {
+    /// 记录一次观测
+    pub fn measured(&mut self, source: MeasurementSource, time: Instant) {
+        self.last = Some((source, time));
+        if source == MeasurementSource::Gnss {
+            self.last_gnss = Some(time);
+        }
+    }
+
+    /// 最近一次计算的定位质量
+    #[inline]
+    pub fn quality(&self) -> Option<LocalizationQuality> {
+        self.quality
+    }
+
+    /// 以粒子统计量更新，返回定位质量和收敛状态是否改变
+    pub fn update(
+        &mut self,
+        stats: Option<Statistics>,
+        count: usize,
+        target_count: usize,
+        now: Instant,
+    ) -> (LocalizationQuality, bool) {
+        let since_gnss = self.last_gnss.map(|t| now.saturating_duration_since(t));
+        let (covariance, heading_variance, effective) = match stats {
+            Some(s) => (s.covariance, s.heading_variance, s.effective),
+            None => (
+                Matrix2::from_diagonal_element(f32::INFINITY),
+                f32::INFINITY,
+                0.0,
+            ),
+        };
+        let spread = covariance.trace().sqrt();
+        let source = match self.last {
+            Some((source, t)) if now.saturating_duration_since(t) < MEASUREMENT_TIMEOUT => source,
+            _ => MeasurementSource::Odometry,
+        };
+        // 没有外部观测时，粒子收拢也不代表位姿可信
+        let blind = source == MeasurementSource::Odometry;
+        let good = !blind && spread < CONVERGED_SPREAD && heading_variance < CONVERGED_HEADING;
+        let bad = blind || spread > DIVERGED_SPREAD || heading_variance > DIVERGED_HEADING;
+        let last = self.convergence;
+        match self.convergence {
+            Convergence::Converged => {
+                if bad {
+                    self.convergence = Convergence::Diverged;
+                    self.count = 0;
+                }
+            }
+            Convergence::Converging | Convergence::Diverged => {
+                self.count = if good { self.count + 1 } else { 0 };
+                if self.count >= CONVERGED_COUNT {
+                    self.convergence = Convergence::Converged;
+                }
+            }
+        }
+        let quality = LocalizationQuality {
+            covariance,
+            heading_variance,
+            effective,
+            count,
+            target_count,
+            source,
+            since_gnss,
+            convergence: self.convergence,
+        };
+        self.quality = Some(quality);
+        (quality, last != self.convergence)
+    }
+
+    /// 重新初始化，需要重新收敛，返回收敛状态是否改变
+    pub fn reset(&mut self) -> bool {
+        let changed = self.convergence != Convergence::Converging;
+        self.convergence = Convergence::Converging;
+        self.count = 0;
+        if let Some(ref mut q) = self.quality {
+            q.convergence = Convergence::Converging;
+        }
+        changed
+    }
+}
+
+/// 定位质量不足的原因
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DegradeReason {
+    /// 定位尚未收敛或已发散
+    NotConverged,
+    /// 位置标准差过大（m）
+    Spread(f32),
+    /// GNSS 中断时间过长
+    GnssLost(Duration),
+}
+
+/// 循迹因定位质量受到的限制
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrackingRestriction {
+    Slowed(DegradeReason),
+    Paused(DegradeReason),
+    /// 恢复正常循迹
+    Resumed,
+    /// 暂停时间过长，放弃循迹
+    Aborted(DegradeReason),
+}
+
+/// 循迹和录制对定位质量的要求
+#[derive(Clone, Copy, Debug)]
+pub struct QualityGate {
+    /// 位置标准差超过此值时减速（m）
+    pub slow_spread: f32,
+    /// 减速时的最大速度（m/s）
+    pub slow_speed: f32,
+    /// 位置标准差超过此值时暂停（m）
+    pub pause_spread: f32,
+    /// GNSS 中断超过此时间时减速
+    pub gnss_timeout: Duration,
+    /// 暂停超过此时间时放弃循迹，`None` 表示一直等待
+    pub abort_timeout: Option<Duration>,
+}
+
+impl Default for QualityGate {
+    fn default() -> Self {
+        Self {
+            slow_spread: 0.2,
+            slow_speed: 0.3,
+            pause_spread: 0.5,
+            gnss_timeout: Duration::from_secs(5),
+            abort_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Severity {
+    Slow,
+    Pause,
+}
+
+impl QualityGate {
+    /// 判断定位质量是否不足
+    fn degrade(&self, q: &LocalizationQuality) -> Option<(Severity, DegradeReason)> {
+        let spread = q.spread();
+        if q.convergence != Convergence::Converged {
+            Some((Severity::Pause, DegradeReason::NotConverged))
+        } else if spread > self.pause_spread {
+            Some((Severity::Pause, DegradeReason::Spread(spread)))
+        } else if spread > self.slow_spread {
+            Some((Severity::Slow, DegradeReason::Spread(spread)))
+        } else {
+            q.since_gnss
+                .filter(|d| *d > self.gnss_timeout)
+                .map(|d| (Severity::Slow, DegradeReason::GnssLost(d)))
+        }
+    }
+
+    /// 定位质量是否足以录制路径
+    #[inline]
+    pub fn allows_recording(&self, q: &LocalizationQuality) -> bool {
+        self.degrade(q).is_none()
+    }
+}
+
+/// 循迹在当前定位质量下的动作
+pub(super) enum GateAction {
+    /// 以不超过给定速度行驶
+    Drive(f32),
+    Pause,
+    Abort,
+}
+
+/// 跟踪循迹受限状态
+#[derive(Default)]
+pub(super) struct GateMonitor {
+    pub config: QualityGate,
+    state: Option<(Severity, DegradeReason)>,
+    paused_since: Option<Instant>,
+}
+
+impl GateMonitor {
+    /// 检查定位质量，状态改变时返回事件
+    ///
+    /// 尚未计算出定位质量时视为未收敛
+    pub fn check(
+        &mut self,
+        quality: Option<LocalizationQuality>,
+        now: Instant,
+    ) -> (GateAction, Option<TrackingRestriction>) {
+        let state = match quality {
+            Some(q) => self.config.degrade(&q),
+            None => Some((Severity::Pause, DegradeReason::NotConverged)),
+        };
+        // 原因的数值变化不算状态改变
+        let changed = match (self.state, state) {
+            (Some((a, x)), Some((b, y))) => {
+                a != b || std::mem::discriminant(&x) != std::mem::discriminant(&y)
+            }
+            (None, None) => false,
+            _ => true,
+        };
+        self.state = state;
+        let event = match state {
+            Some((Severity::Slow, reason)) => TrackingRestriction::Slowed(reason),
+            Some((Severity::Pause, reason)) => TrackingRestriction::Paused(reason),
+            None => TrackingRestriction::Resumed,
+        };
+        let event = if changed { Some(event) } else { None };
+        match state {
+            None => {
+                self.paused_since = None;
+                (GateAction::Drive(f32::INFINITY), event)
+            }
+            Some((Severity::Slow, _)) => {
+                self.paused_since = None;
+                (GateAction::Drive(self.config.slow_speed), event)
+            }
+            Some((Severity::Pause, reason)) => {
+                let since = *self.paused_since.get_or_insert(now);
+                match self.config.abort_timeout {
+                    Some(timeout) if now.saturating_duration_since(since) > timeout => {
+                        self.reset();
+                        (
+                            GateAction::Abort,
+                            Some(TrackingRestriction::Aborted(reason)),
+                        )
+                    }
+                    _ => (GateAction::Pause, event),
+                }
+            }
+        }
+    }
+
+    /// 开始或结束循迹时清除状态
+    #[inline]
+    pub fn reset(&mut self) {
+        self.state = None;
+        self.paused_since = None;
+    }
+}
+
+#[test]
+fn test() {
+    let now = Instant::now();
+    let stats = |spread: f32| Statistics {
+        covariance: Matrix2::from_diagonal_element(spread * spread / 2.0),
+        heading_variance: 0.01,
+        effective: 50.0,
+    };
+    let mut monitor = QualityMonitor::default();
+    monitor.measured(MeasurementSource::Gnss, now);
+    for _ in 1..CONVERGED_COUNT {
+        assert!(!monitor.update(Some(stats(0.1)), 80, 80, now).1);
+    }
+    let (quality, changed) = monitor.update(Some(stats(0.1)), 80, 80, now);
+    assert!(changed && quality.is_trustworthy());
+    let (quality, changed) = monitor.update(Some(stats(2.0)), 80, 80, now);
+    assert!(changed);
+    assert_eq!(Convergence::Diverged, quality.convergence);
+    // 尚未计算出定位质量时不能循迹
+    let mut gate = GateMonitor::default();
+    assert!(matches!(gate.check(None, now).0, GateAction::Pause));
+}
@@ -1,10 +1,10 @@
-﻿use super::send_async;
+﻿use super::{gnss_model::GgaQuality, send_async};
 use async_std::{
     channel::{unbounded, Receiver},
-    fs::File,
-    io::{prelude::BufReadExt, BufReader},
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    io,
+    path::{Path, PathBuf},
+    prelude::FutureExt,
+    sync::{Arc, Condvar, Mutex},
     task,
 };
 use lazy_static::lazy_static;
@@ -13,37 +13,149 @@ use rtk_qxwz::{
     encode_base64, Gpgga, GpggaParseError::*, GpggaSender, QXWZAccount, QXWZService, RTCMReceiver,
     RTKBoard,
 };
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+
+mod caster;
+mod credential;
+mod ntrip;
+
+use caster::{Caster, CasterConfig, CASTER_FILE};
+use credential::{AuthReporter, Backoff};
+use ntrip::{NtripClient, NtripConfig, NTRIP_FILE};
+
+pub use caster::CasterStats;
+pub use credential::{validate as validate_credential, AuthFailure, CredentialError, RtkAuth};
 
 pub(super) enum Event {
     SerialConnected,
     SerialDisconnected,
     TcpConnected,
     TcpDisconnected,
-    Gpgga(Instant, Gpgga),
+    Gpgga(Instant, Gpgga, GgaQuality),
+    Auth(RtkAuth),
+    CasterFailed(io::Error),
+}
+
+/// 向差分服务上传 GGA 的通道
+enum GgaUplink {
+    Qxwz(GpggaSender),
+    Ntrip(ntrip::GgaSender),
+}
+
+impl GgaUplink {
+    async fn send(&mut self, line: &str) {
+        match self {
+            Self::Qxwz(s) => s.send(line).await,
+            Self::Ntrip(s) => s.send(line).await,
+        }
+    }
 }
 
 pub(super) fn supervisor(dir: PathBuf) -> Receiver<Event> {
     let _ = *task::block_on(UPDATE_TIME.lock());
-    *task::block_on(FILE_PATH.lock()) = dir;
-    let gpgga: Arc<Mutex<Option<GpggaSender>>> = Arc::new(Mutex::new(None));
+    let ntrip = task::block_on(NtripConfig::load(&dir.join(NTRIP_FILE)));
+    *task::block_on(FILE_PATH.lock()) = dir.clone();
+    let gpgga: Arc<Mutex<Option<GgaUplink>>> = Arc::new(Mutex::new(None));
     let rtcm: Arc<Mutex<Option<RTCMReceiver>>> = Arc::new(Mutex::new(None));
     let (sender, receiver) = unbounded();
-    {
+    // 配置了本地转发时，把收到的差分数据转发给其他机器人
+    if let Some(config) = task::block_on(CasterConfig::load(&dir.join(CASTER_FILE))) {
+        match task::block_on(Caster::bind(config)) {
+            Ok(caster) => *task::block_on(CASTER.lock()) = Some(caster),
+            Err(e) => task::block_on(send_async!(Event::CasterFailed(e) => sender)),
+        }
+    }
+    // 配置了 NTRIP 时代替千寻服务
+    if let Some(config) = ntrip {
         let gpgga = gpgga.clone();
         let rtcm = rtcm.clone();
         let sender = sender.clone();
+        let auth = AuthReporter::new(sender.clone());
 
+        if config.user.is_empty() {
+            task::spawn(credential::watch_expiry(dir.clone(), auth.clone()));
+        }
+        task::spawn(async move {
+            let mut backoff = Backoff::default();
+            loop {
+                let time = Instant::now();
+                // 配置文件中没有用户时，有账号文件则使用账号文件，否则匿名连接
+                let mut config = config.clone();
+                if config.user.is_empty() {
+                    match credential::load(&dir).await {
+                        Some(c) if c.is_expired() => {
+                            auth.report(RtkAuth::Expired).await;
+                            credential::wait_reauth(time, None).await;
+                            backoff.reset();
+                            continue;
+                        }
+                        Some(c) => {
+                            let (user, password) = c.split();
+                            config.user = user.into();
+                            config.password = password.into();
+                        }
+                        None => {}
+                    }
+                }
+                match NtripClient::connect(&config).await {
+                    Ok((mut client, uplink)) => {
+                        backoff.reset();
+                        auth.report(RtkAuth::Succeeded).await;
+                        send_async!(Event::TcpConnected => sender).await;
+                        *gpgga.lock().await = Some(GgaUplink::Ntrip(uplink));
+                        // 账号更新或过期时立即断开，不必等到下一段数据
+                        let invalidated = || async {
+                            credential::wait_reauth(time, None).await;
+                            io::Result::<Vec<u8>>::Err(io::ErrorKind::Interrupted.into())
+                        };
+                        while let Ok(buf) = client.read().race(invalidated()).await {
+                            if let Some(ref mut receiver) = *rtcm.lock().await {
+                                receiver.receive(buf.as_slice());
+                            }
+                            broadcast(&buf).await;
+                        }
+                        *gpgga.lock().await = None;
+                        send_async!(Event::TcpDisconnected => sender).await;
+                    }
+                    // 账号被拒绝，等待更换账号
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                        auth.report(RtkAuth::Failed(AuthFailure::Rejected)).await;
+                        credential::wait_reauth(time, None).await;
+                        backoff.reset();
+                        continue;
+                    }
+                    Err(_) => {
+                        auth.report(RtkAuth::Failed(AuthFailure::Unreachable)).await;
+                    }
+                }
+                backoff.wait(time).await;
+            }
+        });
+    } else {
+        let gpgga = gpgga.clone();
+        let rtcm = rtcm.clone();
+        let sender = sender.clone();
+        let auth = AuthReporter::new(sender.clone());
+
+        task::spawn(credential::watch_expiry(dir.clone(), auth.clone()));
         task::spawn_blocking(move || {
             let mut account = String::new();
+            let mut backoff = Backoff::default();
             loop {
                 let time = Instant::now();
+                if !task::block_on(credential_ready(&dir, &auth, time)) {
+                    backoff.reset();
+                    continue;
+                }
+                let expires = task::block_on(credential::load(&dir)).and_then(|c| c.expires);
                 SupervisorForSingle::<QXWZService<AuthFile>>::default().join(|e| {
                     task::block_on(async {
                         match e {
                             Connected(key, stream) => {
+                                backoff.reset();
+                                auth.report(RtkAuth::Succeeded).await;
                                 send_async!(Event::TcpConnected => sender).await;
-                                *gpgga.lock().await = Some(stream.get_sender());
+                                *gpgga.lock().await = Some(GgaUplink::Qxwz(stream.get_sender()));
                                 account = key;
                             }
                             Disconnected => {
@@ -54,14 +166,17 @@ pub(super) fn supervisor(dir: PathBuf) -> Receiver<Event> {
                                 if let Some(ref mut receiver) = *rtcm.lock().await {
                                     receiver.receive(buf.as_slice());
                                 }
+                                broadcast(&buf).await;
                             }
                             Event(_, None) => {}
+                            // 千寻服务不区分认证失败和网络故障
                             ConnectFailed => {
-                                task::sleep(Duration::from_secs(3)).await;
+                                auth.report(RtkAuth::Failed(AuthFailure::Unreachable)).await;
+                                backoff.wait(time).await;
                             }
                         }
                         *UPDATE_TIME.lock().await < time
-                            && time < Instant::now() + Duration::from_secs(300)
+                            && expires.map_or(true, |t| SystemTime::now() < t)
                     })
                 });
                 task::block_on(send_async!(Event::TcpDisconnected => sender));
@@ -82,7 +197,8 @@ pub(super) fn supervisor(dir: PathBuf) -> Receiver<Event> {
                     }
                     Event(_, Some((t, line))) => match line.parse::<Gpgga>() {
                         Ok(body) => {
-                            send_async!(Event::Gpgga(t, body) => sender).await;
+                            let quality = GgaQuality::parse(&line);
+                            send_async!(Event::Gpgga(t, body, quality) => sender).await;
                             if let Some(ref mut s) = *gpgga.lock().await {
                                 s.send(&line).await;
                             }
@@ -108,12 +224,62 @@ pub(super) fn supervisor(dir: PathBuf) -> Receiver<Event> {
 
 lazy_static! {
     static ref UPDATE_TIME: Mutex<Instant> = Mutex::new(Instant::now());
+    static ref UPDATED: Condvar = Condvar::new();
     static ref FILE_PATH: Mutex<PathBuf> = Default::default();
+    static ref CASTER: Mutex<Option<Caster>> = Default::default();
+}
+
+/// 向本地转发服务的客户端转发差分数据
+async fn broadcast(buf: &[u8]) {
+    let caster = CASTER.lock().await.clone();
+    if let Some(caster) = caster {
+        caster.broadcast(buf).await;
+    }
+}
+
+/// 本地转发服务各客户端连接的统计，未启用转发时为空
+pub async fn caster_stats() -> Vec<CasterStats> {
+    let caster = CASTER.lock().await.clone();
+    match caster {
+        Some(caster) => caster.stats().await,
+        None => Vec::new(),
+    }
 }
 
 #[inline]
 pub async fn reauth() {
     *UPDATE_TIME.lock().await = Instant::now();
+    UPDATED.notify_all();
+}
+
+/// 保存账号并以新账号重连
+pub async fn set_credential(
+    dir: &Path,
+    account: &str,
+    expires: Option<SystemTime>,
+) -> Result<(), CredentialError> {
+    credential::save(dir, account, expires).await?;
+    reauth().await;
+    Ok(())
+}
+
+/// 删除账号并断开差分服务
+pub async fn clear_credential(dir: &Path) -> io::Result<()> {
+    credential::clear(dir).await?;
+    reauth().await;
+    Ok(())
+}
+
+/// 检查账号是否存在且未过期，不可用时报告并等待更换账号
+async fn credential_ready(dir: &Path, auth: &AuthReporter, time: Instant) -> bool {
+    let state = match credential::load(dir).await {
+        None => RtkAuth::Failed(AuthFailure::Missing),
+        Some(c) if c.is_expired() => RtkAuth::Expired,
+        Some(_) => return true,
+    };
+    auth.report(state).await;
+    credential::wait_reauth(time, None).await;
+    false
 }
 
 struct AuthFile;
@@ -121,18 +287,10 @@ struct AuthFile;
 impl QXWZAccount for AuthFile {
     fn get() -> Option<String> {
         task::block_on(async {
-            let path = FILE_PATH.lock().await.join("auth");
-            match File::open(&path).await {
-                Ok(file) => {
-                    let mut reader = BufReader::new(file);
-                    let mut line = String::new();
-                    match reader.read_line(&mut line).await {
-                        Ok(0) | Err(_) => None,
-                        Ok(_) => Some(encode_base64(line.trim_end())),
-                    }
-                }
-                Err(_) => None,
-            }
+            let dir = FILE_PATH.lock().await.clone();
+            credential::load(&dir)
+                .await
+                .map(|c| encode_base64(&c.account))
         })
     }
 }
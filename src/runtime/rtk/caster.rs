@@ -0,0 +1,292 @@
+﻿use async_std::{
+    channel::{bounded, Sender},
+    fs, io,
+    io::{prelude::BufReadExt, BufReader, ReadExt, WriteExt},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    task,
+};
+use rtk_qxwz::encode_base64;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub(super) const CASTER_FILE: &str = "caster"; // 本地差分转发配置文件
+
+const MAX_HEADER: usize = 64; // 请求头最多行数
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10); // 接入后必须在此时间内发完请求头
+const CLIENT_QUEUE: usize = 16; // 每个客户端最多积压的差分数据段数，超过时断开
+const UNAUTHORIZED: &[u8] =
+    b"HTTP/1.0 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"NTRIP\"\r\n\r\n";
+
+/// 转发服务中一个客户端连接的统计
+#[derive(Clone, Debug)]
+pub struct CasterStats {
+    /// 客户端地址
+    pub peer: SocketAddr,
+    pub user: String,
+    /// 已连接的时间
+    pub connected: Duration,
+    /// 已发送字节数
+    pub bytes: u64,
+}
+
+/// 本地差分转发配置
+///
+/// 配置文件每行一项：`port 2101`、`mountpoint RTCM3`，以及一行或多行 `user <用户名> <密码>`
+#[derive(Clone, Debug)]
+pub(super) struct CasterConfig {
+    pub port: u16,
+    pub mountpoint: String,
+    /// 用户名和编码后的认证信息
+    users: Vec<(String, String)>,
+}
+
+impl CasterConfig {
+    /// 读取配置文件，不存在或没有用户时返回 `None`
+    pub async fn load(path: &Path) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).await.ok()?)
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut port = 2101;
+        let mut mountpoint = None;
+        let mut users = Vec::new();
+        for line in text.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["port", p] => port = p.parse().ok()?,
+                ["mountpoint", m] => mountpoint = Some(m.trim_start_matches('/').to_string()),
+                ["user", user, password] => users.push((
+                    user.to_string(),
+                    encode_base64(&format!("{}:{}", user, password)),
+                )),
+                _ => {}
+            }
+        }
+        if users.is_empty() {
+            None
+        } else {
+            Some(Self {
+                port,
+                mountpoint: mountpoint?,
+                users,
+            })
+        }
+    }
+}
+
+/// 向本地客户端转发差分数据的 NTRIP 服务
+#[derive(Clone)]
+pub(super) struct Caster(Arc<Inner>);
+
+struct Inner {
+    config: CasterConfig,
+    /// 各客户端的发送队列，队列满时通过连接将其断开
+    clients: Mutex<Vec<(Sender<Arc<[u8]>>, TcpStream)>>,
+    stats: Mutex<HashMap<SocketAddr, Client>>,
+}
+
+/// 已连接的客户端
+struct Client {
+    user: String,
+    since: Instant,
+    bytes: u64,
+}
+
+impl Caster {
+    /// 监听端口并开始接受连接
+    pub async fn bind(mut config: CasterConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", config.port)).await?;
+        // 配置为 0 时由系统分配端口
+        config.port = listener.local_addr()?.port();
+        let caster = Self(Arc::new(Inner {
+            config,
+            clients: Default::default(),
+            stats: Default::default(),
+        }));
+        let clone = caster.clone();
+        task::spawn(async move {
+            loop {
+                if let Ok((stream, peer)) = listener.accept().await {
+                    task::spawn(clone.clone().serve(stream, peer));
+                }
+            }
+        });
+        Ok(caster)
+    }
+
+    /// 实际监听的端口
+    #[cfg(test)]
+    #[inline]
+    pub fn port(&self) -> u16 {
+        self.0.config.port
+    }
+
+    /// 转发一段差分数据，不等待客户端接收，积压过多的客户端被断开
+    pub async fn broadcast(&self, buf: &[u8]) {
+        let buf = Arc::<[u8]>::from(buf);
+        self.0.clients.lock().await.retain(|(sender, stream)| {
+            let ok = sender.try_send(buf.clone()).is_ok();
+            if !ok {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            ok
+        });
+    }
+
+    /// 当前连接的各客户端的统计
+    pub async fn stats(&self) -> Vec<CasterStats> {
+        self.0
+            .stats
+            .lock()
+            .await
+            .iter()
+            .map(|(peer, client)| CasterStats {
+                peer: *peer,
+                user: client.user.clone(),
+                connected: client.since.elapsed(),
+                bytes: client.bytes,
+            })
+            .collect()
+    }
+
+    async fn serve(self, mut stream: TcpStream, peer: SocketAddr) {
+        let mut reader = BufReader::new(stream.clone());
+        // 连接后不发请求的客户端不能一直占用任务
+        let request = io::timeout(HANDSHAKE_TIMEOUT, read_request(&mut reader)).await;
+        let (path, auth, v2) = match request {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+        let config = &self.0.config;
+        // 挂载点不对时返回源列表
+        if path != config.mountpoint {
+            let table = format!(
+                "STR;{0};{0};RTCM 3;;2;;;;0.00;0.00;1;0;robot-bin;none;B;N;0;\r\nENDSOURCETABLE\r\n",
+                config.mountpoint
+            );
+            let response = format!(
+                "SOURCETABLE 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+                table.len(),
+                table
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            return;
+        }
+        let user = match config.users.iter().find(|(_, a)| Some(a) == auth.as_ref()) {
+            Some((user, _)) => user.clone(),
+            None => {
+                let _ = stream.write_all(UNAUTHORIZED).await;
+                return;
+            }
+        };
+        // 先登记再应答，应答后的数据都能收到
+        let (sender, receiver) = bounded::<Arc<[u8]>>(CLIENT_QUEUE);
+        self.0.clients.lock().await.push((sender, stream.clone()));
+        self.0.stats.lock().await.insert(
+            peer,
+            Client {
+                user,
+                since: Instant::now(),
+                bytes: 0,
+            },
+        );
+        let response: &[u8] = if v2 {
+            b"HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\nCache-Control: no-store\r\n\r\n"
+        } else {
+            b"ICY 200 OK\r\n\r\n"
+        };
+        if stream.write_all(response).await.is_ok() {
+            // 丢弃客户端上传的 GGA
+            task::spawn(async move {
+                let mut buf = [0u8; 256];
+                while let Ok(n) = reader.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                }
+            });
+            while let Ok(buf) = receiver.recv().await {
+                if stream.write_all(&buf).await.is_err() {
+                    break;
+                }
+                if let Some(client) = self.0.stats.lock().await.get_mut(&peer) {
+                    client.bytes += buf.len() as u64;
+                }
+            }
+        }
+        self.0.stats.lock().await.remove(&peer);
+    }
+}
+
+/// 读取请求，返回挂载点、认证信息和是否为 NTRIP 2.0
+async fn read_request(
+    reader: &mut BufReader<TcpStream>,
+) -> io::Result<(String, Option<String>, bool)> {
+    let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut words = line.split_whitespace();
+    if words.next() != Some("GET") {
+        return Err(invalid());
+    }
+    let path = words
+        .next()
+        .ok_or_else(invalid)?
+        .trim_start_matches('/')
+        .to_string();
+    let mut auth = None;
+    let mut v2 = false;
+    for _ in 0..MAX_HEADER {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok((path, auth, v2));
+        }
+        if let Some((key, value)) = header.split_once(':') {
+            let (key, value) = (key.trim(), value.trim());
+            if key.eq_ignore_ascii_case("authorization") {
+                auth = value.strip_prefix("Basic ").map(|s| s.trim().to_string());
+            } else if key.eq_ignore_ascii_case("ntrip-version") {
+                v2 = value.eq_ignore_ascii_case("ntrip/2.0");
+            }
+        }
+    }
+    Err(invalid())
+}
+
+#[test]
+fn test() {
+    use super::ntrip::{NtripClient, NtripConfig, NtripVersion};
+    use std::time::Duration;
+
+    task::block_on(async {
+        let config = CasterConfig::parse("port 0\nmountpoint RTCM3\nuser rover secret\n").unwrap();
+        let caster = Caster::bind(config).await.unwrap();
+        let client = |password: &str| NtripConfig {
+            host: "127.0.0.1".into(),
+            port: caster.port(),
+            mountpoint: "RTCM3".into(),
+            user: "rover".into(),
+            password: password.into(),
+            version: NtripVersion::V1,
+        };
+        let denied = NtripClient::connect(&client("wrong")).await.err().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, denied.kind());
+
+        let (mut rover, _) = NtripClient::connect(&client("secret")).await.unwrap();
+        caster.broadcast(b"\xd3\x00\x13").await;
+        assert_eq!(b"\xd3\x00\x13", rover.read().await.unwrap().as_slice());
+        task::sleep(Duration::from_millis(50)).await;
+        let stats = caster.stats().await;
+        assert_eq!(1, stats.len());
+        assert_eq!("rover", stats[0].user);
+        assert_eq!(3, stats[0].bytes);
+    });
+}
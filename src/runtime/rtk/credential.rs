@@ -0,0 +1,213 @@
+﻿use super::{reauth, send_async, Event, UPDATED, UPDATE_TIME};
+use async_std::{
+    channel::Sender,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub(super) const AUTH_FILE: &str = "auth"; // 差分服务账号文件
+
+const MIN_BACKOFF: Duration = Duration::from_secs(3); // 首次重连等待
+const MAX_BACKOFF: Duration = Duration::from_secs(300); // 最长重连等待
+const MAX_EXPIRY_WAIT: Duration = Duration::from_secs(600); // 等待账号到期的最长时间，容纳系统时间校正
+
+/// 差分服务认证状态
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RtkAuth {
+    Succeeded,
+    Failed(AuthFailure),
+    /// 账号已过期，需要更换
+    Expired,
+}
+
+/// 认证失败的原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuthFailure {
+    /// 没有设置账号
+    Missing,
+    /// 服务器拒绝了账号
+    Rejected,
+    /// 无法连接服务器，或服务器未说明原因
+    Unreachable,
+}
+
+/// 账号格式错误
+#[derive(Debug)]
+pub enum CredentialError {
+    /// 应为 `用户名:密码` 且不含空白字符
+    InvalidFormat,
+    Io(io::Error),
+}
+
+impl From<io::Error> for CredentialError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// 已保存的账号
+pub(super) struct Credential {
+    /// `用户名:密码`
+    pub account: String,
+    pub expires: Option<SystemTime>,
+}
+
+impl Credential {
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        self.expires.map_or(false, |t| SystemTime::now() >= t)
+    }
+
+    /// 拆分为用户名和密码
+    #[inline]
+    pub fn split(&self) -> (&str, &str) {
+        self.account.split_once(':').unwrap_or((&self.account, ""))
+    }
+}
+
+/// 检查账号格式
+pub fn validate(account: &str) -> Result<(), CredentialError> {
+    let valid = matches!(
+        account.split_once(':'),
+        Some((user, password)) if !user.is_empty() && !password.is_empty()
+    );
+    if valid && !account.contains(char::is_whitespace) {
+        Ok(())
+    } else {
+        Err(CredentialError::InvalidFormat)
+    }
+}
+
+/// 读取账号，首行为账号，可选的第二行为 `expires <unix 秒>`
+pub(super) async fn load(dir: &Path) -> Option<Credential> {
+    let text = fs::read_to_string(dir.join(AUTH_FILE)).await.ok()?;
+    let mut lines = text.lines();
+    let account = lines.next()?.trim().to_string();
+    if account.is_empty() {
+        return None;
+    }
+    let expires = lines
+        .filter_map(|line| line.trim().strip_prefix("expires "))
+        .filter_map(|secs| secs.trim().parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .next();
+    Some(Credential { account, expires })
+}
+
+/// 先写临时文件再替换，避免写到一半的账号被读取
+pub(super) async fn save(
+    dir: &Path,
+    account: &str,
+    expires: Option<SystemTime>,
+) -> Result<(), CredentialError> {
+    validate(account)?;
+    let mut text = format!("{}\n", account);
+    if let Some(t) = expires {
+        let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        text += &format!("expires {}\n", secs);
+    }
+    let path = dir.join(AUTH_FILE);
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, text).await?;
+    fs::rename(temp, path).await?;
+    Ok(())
+}
+
+pub(super) async fn clear(dir: &Path) -> io::Result<()> {
+    match fs::remove_file(dir.join(AUTH_FILE)).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// 认证状态改变时报告，克隆的报告者共享状态
+#[derive(Clone)]
+pub(super) struct AuthReporter {
+    sender: Sender<Event>,
+    last: Arc<Mutex<Option<RtkAuth>>>,
+}
+
+impl AuthReporter {
+    #[inline]
+    pub fn new(sender: Sender<Event>) -> Self {
+        Self {
+            sender,
+            last: Default::default(),
+        }
+    }
+
+    pub async fn report(&self, auth: RtkAuth) {
+        let mut last = self.last.lock().await;
+        if *last != Some(auth) {
+            *last = Some(auth);
+            send_async!(Event::Auth(auth) => self.sender).await;
+        }
+    }
+}
+
+/// 指数退避的重连等待
+pub(super) struct Backoff(Duration);
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self(MIN_BACKOFF)
+    }
+}
+
+impl Backoff {
+    #[inline]
+    pub fn reset(&mut self) {
+        self.0 = MIN_BACKOFF;
+    }
+
+    /// 等待当前间隔并加倍，账号更新时立即返回并复位
+    pub async fn wait(&mut self, since: Instant) {
+        let delay = self.0;
+        self.0 = Duration::min(delay * 2, MAX_BACKOFF);
+        if wait_reauth(since, Some(delay)).await {
+            self.reset();
+        }
+    }
+}
+
+/// 在账号到期时报告并触发重连，不依赖差分数据的到达
+///
+/// 只在到期时刻和账号更新时读取账号文件
+pub(super) async fn watch_expiry(dir: PathBuf, auth: AuthReporter) {
+    let mut expired = false;
+    loop {
+        let since = Instant::now();
+        let credential = load(&dir).await;
+        let now = credential.as_ref().map_or(false, Credential::is_expired);
+        if now && !expired {
+            auth.report(RtkAuth::Expired).await;
+            reauth().await;
+        }
+        expired = now;
+        // 等到到期时刻，已过期或没有期限时只等待账号更新
+        let remaining = credential
+            .and_then(|c| c.expires)
+            .and_then(|t| t.duration_since(SystemTime::now()).ok())
+            .map(|d| d.min(MAX_EXPIRY_WAIT));
+        wait_reauth(since, remaining).await;
+    }
+}
+
+/// 等待账号更新，返回是否已更新
+pub(super) async fn wait_reauth(since: Instant, timeout: Option<Duration>) -> bool {
+    let time = UPDATE_TIME.lock().await;
+    match timeout {
+        Some(timeout) => {
+            let (time, _) = UPDATED
+                .wait_timeout_until(time, timeout, |t| *t > since)
+                .await;
+            *time > since
+        }
+        None => {
+            UPDATED.wait_until(time, |t| *t > since).await;
+            true
+        }
+    }
+}
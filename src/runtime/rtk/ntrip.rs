@@ -0,0 +1,298 @@
+﻿use async_std::{
+    fs, io,
+    io::{prelude::BufReadExt, BufReader, ReadExt, WriteExt},
+    net::TcpStream,
+    path::Path,
+};
+use rtk_qxwz::encode_base64;
+use std::time::Duration;
+
+pub(super) const NTRIP_FILE: &str = "ntrip"; // NTRIP 配置文件，存在时代替千寻服务
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10); // 超过此时间没有差分数据视为断线
+const MAX_HEADER: usize = 64; // 响应头最多行数
+
+/// NTRIP 协议版本
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum NtripVersion {
+    V1,
+    V2,
+}
+
+/// NTRIP 客户端配置
+///
+/// 配置文件每行一项，形如 `host caster.example.com`，可用的键为
+/// `host`、`port`、`mountpoint`、`user`、`password` 和 `version`
+#[derive(Clone, Debug)]
+pub(super) struct NtripConfig {
+    pub host: String,
+    pub port: u16,
+    pub mountpoint: String,
+    pub user: String,
+    pub password: String,
+    pub version: NtripVersion,
+}
+
+impl NtripConfig {
+    /// 读取配置文件，不存在或缺少必要项时返回 `None`
+    pub async fn load(path: &Path) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).await.ok()?)
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut host = None;
+        let mut port = 2101;
+        let mut mountpoint = None;
+        let mut user = String::new();
+        let mut password = String::new();
+        let mut version = NtripVersion::V2;
+        for line in text.lines() {
+            if let Some((key, value)) = line.trim().split_once(char::is_whitespace) {
+                let value = value.trim();
+                match key {
+                    "host" => host = Some(value.to_string()),
+                    "port" => port = value.parse().ok()?,
+                    "mountpoint" => mountpoint = Some(value.trim_start_matches('/').to_string()),
+                    "user" => user = value.to_string(),
+                    "password" => password = value.to_string(),
+                    "version" => {
+                        version = match value {
+                            "1" => NtripVersion::V1,
+                            "2" => NtripVersion::V2,
+                            _ => return None,
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Some(Self {
+            host: host?,
+            port,
+            mountpoint: mountpoint?,
+            user,
+            password,
+            version,
+        })
+    }
+
+    fn request(&self) -> String {
+        let mut request = match self.version {
+            NtripVersion::V1 => format!("GET /{} HTTP/1.0\r\n", self.mountpoint),
+            NtripVersion::V2 => format!(
+                "GET /{} HTTP/1.1\r\nHost: {}:{}\r\nNtrip-Version: Ntrip/2.0\r\nConnection: close\r\n",
+                self.mountpoint, self.host, self.port
+            ),
+        };
+        request += "User-Agent: NTRIP robot-bin\r\n";
+        if !self.user.is_empty() {
+            let auth = encode_base64(&format!("{}:{}", self.user, self.password));
+            request += &format!("Authorization: Basic {}\r\n", auth);
+        }
+        request += "\r\n";
+        request
+    }
+}
+
+/// 连接到 NTRIP 服务器的差分数据流
+pub(super) struct NtripClient {
+    reader: BufReader<TcpStream>,
+    chunked: Option<Dechunker>,
+    buf: Vec<u8>,
+}
+
+/// 向 NTRIP 服务器上传 GGA
+pub(super) struct GgaSender(TcpStream);
+
+impl NtripClient {
+    /// 连接并完成握手
+    pub async fn connect(config: &NtripConfig) -> io::Result<(Self, GgaSender)> {
+        let mut stream = TcpStream::connect((config.host.as_str(), config.port)).await?;
+        stream.write_all(config.request().as_bytes()).await?;
+        let mut reader = BufReader::new(stream.clone());
+        // 状态行
+        let mut line = String::new();
+        io::timeout(READ_TIMEOUT, reader.read_line(&mut line)).await?;
+        check_status(line.trim_end())?;
+        // 响应头
+        let mut chunked = false;
+        for _ in 0..MAX_HEADER {
+            line.clear();
+            if io::timeout(READ_TIMEOUT, reader.read_line(&mut line)).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((key, value)) = header.split_once(':') {
+                chunked |= key.trim().eq_ignore_ascii_case("transfer-encoding")
+                    && value.trim().eq_ignore_ascii_case("chunked");
+            }
+        }
+        Ok((
+            Self {
+                reader,
+                chunked: if chunked {
+                    Some(Dechunker::default())
+                } else {
+                    None
+                },
+                buf: vec![0; 1024],
+            },
+            GgaSender(stream),
+        ))
+    }
+
+    /// 读取一段 RTCM 数据，连接断开或超时返回错误
+    pub async fn read(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            let n = io::timeout(READ_TIMEOUT, self.reader.read(&mut self.buf)).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let data = match self.chunked {
+                Some(ref mut dechunker) => dechunker.feed(&self.buf[..n])?,
+                None => self.buf[..n].to_vec(),
+            };
+            if !data.is_empty() {
+                return Ok(data);
+            }
+        }
+    }
+}
+
+impl GgaSender {
+    pub async fn send(&mut self, line: &str) {
+        let line = format!("{}\r\n", line.trim_end());
+        let _ = self.0.write_all(line.as_bytes()).await;
+    }
+}
+
+/// 检查响应状态行
+fn check_status(line: &str) -> io::Result<()> {
+    let mut words = line.split_whitespace();
+    let protocol = words.next().unwrap_or_default();
+    let code = words.next().unwrap_or_default();
+    match (protocol, code) {
+        ("ICY", "200") => Ok(()),
+        (p, "200") if p.starts_with("HTTP/") => Ok(()),
+        // 挂载点不存在时返回源列表
+        ("SOURCETABLE", _) => Err(io::Error::new(io::ErrorKind::NotFound, line)),
+        (_, "401") => Err(io::Error::new(io::ErrorKind::PermissionDenied, line)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, line)),
+    }
+}
+
+/// HTTP 分块传输解码
+#[derive(Default)]
+struct Dechunker {
+    state: ChunkState,
+    size: String,
+}
+
+#[derive(Clone, Copy)]
+enum ChunkState {
+    Size,
+    Data(usize),
+    /// 块后的换行
+    Tail(usize),
+}
+
+impl Default for ChunkState {
+    fn default() -> Self {
+        Self::Size
+    }
+}
+
+impl Dechunker {
+    fn feed(&mut self, mut bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while let Some((&b, rest)) = bytes.split_first() {
+            match self.state {
+                ChunkState::Size => {
+                    bytes = rest;
+                    if b == b'\n' {
+                        let size = self.size.split(';').next().unwrap_or_default().trim();
+                        let size = usize::from_str_radix(size, 16)
+                            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "chunk"))?;
+                        self.size.clear();
+                        // 长度为零的块表示数据流结束
+                        if size == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        self.state = ChunkState::Data(size);
+                    } else {
+                        self.size.push(b as char);
+                    }
+                }
+                ChunkState::Data(size) => {
+                    let n = usize::min(size, bytes.len());
+                    data.extend_from_slice(&bytes[..n]);
+                    bytes = &bytes[n..];
+                    self.state = if n == size {
+                        ChunkState::Tail(2)
+                    } else {
+                        ChunkState::Data(size - n)
+                    };
+                }
+                ChunkState::Tail(n) => {
+                    bytes = rest;
+                    self.state = if n > 1 {
+                        ChunkState::Tail(n - 1)
+                    } else {
+                        ChunkState::Size
+                    };
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[test]
+fn test() {
+    use async_std::{net::TcpListener, task};
+
+    task::block_on(async {
+        // 模拟服务器，要求认证并以分块传输发送差分数据
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let caster = task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream.clone());
+            let mut request = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.unwrap() > 2 {
+                request.push(line.trim_end().to_string());
+                line.clear();
+            }
+            assert_eq!("GET /RTCM3 HTTP/1.1", request[0]);
+            let auth = format!("Authorization: Basic {}", encode_base64("user:pass"));
+            assert!(request.contains(&auth));
+            let mut stream = stream;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .await
+                .unwrap();
+            stream.write_all(b"3\r\n\xd3\x00\x13\r\n").await.unwrap();
+            stream.write_all(b"2\r\n\x3e\xd0\r\n").await.unwrap();
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            line
+        });
+        let config = NtripConfig::parse(&format!(
+            "host 127.0.0.1\nport {}\nmountpoint /RTCM3\nuser user\npassword pass\n",
+            port
+        ))
+        .unwrap();
+        let (mut client, mut sender) = NtripClient::connect(&config).await.unwrap();
+        let mut rtcm = Vec::new();
+        while rtcm.len() < 5 {
+            rtcm.extend(client.read().await.unwrap());
+        }
+        assert_eq!(b"\xd3\x00\x13\x3e\xd0", rtcm.as_slice());
+        sender.send("$GPGGA,085525.00,,,,,0,00,,,M,,M,,*47").await;
+        assert_eq!("$GPGGA,085525.00,,,,,0,00,,,M,,M,,*47\r\n", caster.await);
+    });
+}
@@ -0,0 +1,205 @@
+﻿use super::{point, vector};
+use parry2d::na::{Isometry2, Point2};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+const VOXEL: f32 = 0.05; // 降采样体素边长
+const MAX_DISTANCE: f32 = 0.3; // 最大匹配距离
+const MAX_ITERATIONS: usize = 20;
+const MIN_PAIRS: usize = 30;
+const MIN_QUALITY: f32 = 0.3; // 最低匹配率
+const MAX_GUESS: f32 = 1.0; // 初值位移超过此值视为位姿被重置
+const ODOMETRY_HISTORY: Duration = Duration::from_secs(2); // 保留的轮速计位姿时长
+
+/// 帧间匹配结果
+#[derive(Clone, Copy, Debug)]
+pub struct ScanMatch {
+    /// 上一帧到当前帧的位姿增量
+    pub delta: Isometry2<f32>,
+    /// 匹配率，即找到对应点的点数占当前帧点数的比例
+    pub quality: f32,
+    /// 对应点平均距离（m）
+    pub residual: f32,
+}
+
+/// 连续点云帧间匹配的里程计
+#[derive(Default)]
+pub(super) struct ScanMatcher {
+    last: Option<(Isometry2<f32>, Grid)>,
+}
+
+/// 带时间戳的轮速计位姿，用于取得点云时刻的位姿
+#[derive(Default)]
+pub(super) struct OdometryHistory(VecDeque<(Instant, Isometry2<f32>)>);
+
+/// 用于查找最近点的栅格
+struct Grid(HashMap<(i32, i32), Vec<Point2<f32>>>);
+
+impl ScanMatcher {
+    /// 匹配新的一帧
+    ///
+    /// `scan` 为机器人坐标系下的点云，`pose` 为同一时刻的轮速计位姿，用于计算初值。
+    /// 不使用融合位姿，以免匹配结果依赖滤波器自身的估计
+    pub fn update(&mut self, scan: &[Point2<f32>], pose: Isometry2<f32>) -> Option<ScanMatch> {
+        let scan = downsample(scan);
+        let result = self.last.as_ref().and_then(|(last_pose, reference)| {
+            let guess = last_pose.inverse() * pose;
+            if guess.translation.vector.norm() < MAX_GUESS {
+                icp(reference, &scan, guess)
+            } else {
+                None
+            }
+        });
+        self.last = Some((pose, Grid::new(&scan)));
+        result
+    }
+}
+
+impl OdometryHistory {
+    /// 累加一次轮速计增量
+    pub fn push(&mut self, t: Instant, delta: Isometry2<f32>) {
+        let pose = self.latest() * delta;
+        self.0.push_back((t, pose));
+        while let Some((front, _)) = self.0.front() {
+            if t.saturating_duration_since(*front) > ODOMETRY_HISTORY {
+                self.0.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// 最新的位姿
+    #[inline]
+    pub fn latest(&self) -> Isometry2<f32> {
+        self.0
+            .back()
+            .map_or_else(Isometry2::identity, |(_, pose)| *pose)
+    }
+
+    /// 不晚于 `t` 的最后一个位姿，`t` 早于全部记录时取最早的位姿
+    pub fn at(&self, t: Instant) -> Isometry2<f32> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(time, _)| *time <= t)
+            .or_else(|| self.0.front())
+            .map_or_else(Isometry2::identity, |(_, pose)| *pose)
+    }
+}
+
+impl Grid {
+    fn new(points: &[Point2<f32>]) -> Self {
+        let mut map = HashMap::<_, Vec<_>>::new();
+        for p in points {
+            map.entry(cell(p, MAX_DISTANCE)).or_default().push(*p);
+        }
+        Self(map)
+    }
+
+    /// 查找距离不超过 `MAX_DISTANCE` 的最近点
+    fn nearest(&self, p: &Point2<f32>) -> Option<(Point2<f32>, f32)> {
+        let (x, y) = cell(p, MAX_DISTANCE);
+        (x - 1..=x + 1)
+            .flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y)))
+            .filter_map(|c| self.0.get(&c))
+            .flatten()
+            .map(|q| (*q, (q - p).norm()))
+            .filter(|(_, d)| *d < MAX_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+    }
+}
+
+#[inline]
+fn cell(p: &Point2<f32>, size: f32) -> (i32, i32) {
+    ((p[0] / size).floor() as i32, (p[1] / size).floor() as i32)
+}
+
+/// 体素降采样，每个体素保留一个点
+fn downsample(points: &[Point2<f32>]) -> Vec<Point2<f32>> {
+    let mut map = HashMap::new();
+    for p in points {
+        map.entry(cell(p, VOXEL)).or_insert(*p);
+    }
+    map.into_values().collect()
+}
+
+/// 点到点 ICP，求使 `reference ≈ T * scan` 的 T
+fn icp(reference: &Grid, scan: &[Point2<f32>], guess: Isometry2<f32>) -> Option<ScanMatch> {
+    if scan.len() < MIN_PAIRS {
+        return None;
+    }
+    let mut transform = guess;
+    let mut pairs = Vec::with_capacity(scan.len());
+    for _ in 0..MAX_ITERATIONS {
+        pairs.clear();
+        pairs.extend(scan.iter().filter_map(|p| {
+            let p = transform * p;
+            reference.nearest(&p).map(|(q, d)| (p, q, d))
+        }));
+        if pairs.len() < MIN_PAIRS {
+            return None;
+        }
+        // 闭式解
+        let n = pairs.len() as f32;
+        let (sp, sq) = pairs.iter().fold(
+            (vector(0.0, 0.0), vector(0.0, 0.0)),
+            |(sp, sq), (p, q, _)| (sp + p.coords, sq + q.coords),
+        );
+        let (cp, cq) = (sp / n, sq / n);
+        let (sxx, sxy, syx, syy) =
+            pairs
+                .iter()
+                .fold((0.0, 0.0, 0.0, 0.0), |(xx, xy, yx, yy), (p, q, _)| {
+                    let (p, q) = (p.coords - cp, q.coords - cq);
+                    (
+                        xx + p[0] * q[0],
+                        xy + p[0] * q[1],
+                        yx + p[1] * q[0],
+                        yy + p[1] * q[1],
+                    )
+                });
+        let theta = f32::atan2(sxy - syx, sxx + syy);
+        let rotation = Isometry2::rotation(theta);
+        let t = cq - (rotation * point(cp[0], cp[1])).coords;
+        let step = Isometry2::new(t, theta);
+        transform = step * transform;
+        if t.norm() < 1e-4 && theta.abs() < 1e-4 {
+            break;
+        }
+    }
+    let quality = pairs.len() as f32 / scan.len() as f32;
+    if quality < MIN_QUALITY {
+        return None;
+    }
+    Some(ScanMatch {
+        delta: transform,
+        quality,
+        residual: pairs.iter().map(|(_, _, d)| d).sum::<f32>() / pairs.len() as f32,
+    })
+}
+
+#[test]
+fn test() {
+    // 错落分布的柱子
+    let scan = (0..100)
+        .map(|i| {
+            let f = i as f32;
+            point(
+                (i % 10) as f32 * 0.4 - 2.0 + 0.1 * (f * 1.7).sin(),
+                (i / 10) as f32 * 0.4 - 2.0 + 0.1 * (f * 2.3).cos(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let motion = Isometry2::new(vector(0.05, -0.03), 0.02);
+    let moved = scan
+        .iter()
+        .map(|p| motion.inverse() * p)
+        .collect::<Vec<_>>();
+    let result = icp(&Grid::new(&scan), &moved, Isometry2::identity()).unwrap();
+    assert!((result.delta.translation.vector - motion.translation.vector).norm() < 1e-3);
+    assert!((result.delta.rotation.angle() - motion.rotation.angle()).abs() < 1e-3);
+    assert_eq!(1.0, result.quality);
+}
@@ -0,0 +1,153 @@
+﻿use parry2d::na::Point2;
+use pm1_sdk::model::Physical;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// 卡滞和打滑检测参数
+#[derive(Clone, Copy, Debug)]
+pub struct StuckConfig {
+    /// 检测窗口
+    pub window: Duration,
+    /// 平均目标速度低于此值不检测（m/s）
+    pub min_speed: f32,
+    /// 轮速计里程低于目标里程的此比例视为卡滞
+    pub stall_ratio: f32,
+    /// 定位位移低于轮速计里程的此比例视为打滑
+    pub slip_ratio: f32,
+    /// 检测到异常时是否停止自动循迹
+    pub stop_tracking: bool,
+}
+
+impl Default for StuckConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(2),
+            min_speed: 0.1,
+            stall_ratio: 0.2,
+            slip_ratio: 0.4,
+            stop_tracking: true,
+        }
+    }
+}
+
+/// 运动异常
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MotionFault {
+    /// 有速度指令，但轮子不转
+    Stuck,
+    /// 轮子在转，但定位不动
+    Slipping,
+}
+
+pub(super) struct Monitor {
+    pub config: StuckConfig,
+    wheels: VecDeque<(Instant, f32, f32)>,
+    poses: VecDeque<(Instant, Point2<f32>)>,
+    fault: Option<MotionFault>,
+}
+
+impl Monitor {
+    #[inline]
+    pub fn new(config: StuckConfig) -> Self {
+        Self {
+            config,
+            wheels: VecDeque::new(),
+            poses: VecDeque::new(),
+            fault: None,
+        }
+    }
+
+    /// 更新轮速计，`target` 为控制源给出的原始目标，`s` 为本次里程增量
+    ///
+    /// 进入新的异常状态时返回异常
+    pub fn update_wheels(&mut self, t: Instant, target: Physical, s: f32) -> Option<MotionFault> {
+        let speed = if target.is_released() {
+            0.0
+        } else {
+            target.speed.abs()
+        };
+        self.wheels.push_back((t, speed, s.abs()));
+        let window = self.config.window;
+        while let Some((front, _, _)) = self.wheels.front() {
+            if t.saturating_duration_since(*front) > window {
+                self.wheels.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.check()
+    }
+
+    /// 更新滤波器位置，只应在 GNSS 观测可靠时传入
+    pub fn update_pose(&mut self, t: Instant, p: Point2<f32>) -> Option<MotionFault> {
+        self.poses.push_back((t, p));
+        let window = self.config.window;
+        while let Some((front, _)) = self.poses.front() {
+            if t.saturating_duration_since(*front) > window {
+                self.poses.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.check()
+    }
+
+    fn check(&mut self) -> Option<MotionFault> {
+        let fault = self.stall().or_else(|| self.slip());
+        if fault == self.fault {
+            None
+        } else {
+            self.fault = fault;
+            fault
+        }
+    }
+
+    /// 检查卡滞：目标里程与轮速计里程比较
+    fn stall(&self) -> Option<MotionFault> {
+        let (begin, end) = (self.wheels.front()?.0, self.wheels.back()?.0);
+        let span = end.saturating_duration_since(begin);
+        if span * 2 < self.config.window {
+            return None;
+        }
+        let (expected, actual, _) = self.wheels.iter().fold(
+            (0.0, 0.0, begin),
+            |(expected, actual, last), (t, speed, s)| {
+                (
+                    expected + speed * t.saturating_duration_since(last).as_secs_f32(),
+                    actual + s,
+                    *t,
+                )
+            },
+        );
+        if expected > self.config.min_speed * span.as_secs_f32()
+            && actual < expected * self.config.stall_ratio
+        {
+            Some(MotionFault::Stuck)
+        } else {
+            None
+        }
+    }
+
+    /// 检查打滑：轮速计里程与定位位移比较
+    fn slip(&self) -> Option<MotionFault> {
+        let ((t0, p0), (t1, p1)) = (self.poses.front()?, self.poses.back()?);
+        if t1.saturating_duration_since(*t0) * 2 < self.config.window {
+            return None;
+        }
+        let wheels = self
+            .wheels
+            .iter()
+            .filter(|(t, _, _)| t0 < t && t <= t1)
+            .map(|(_, _, s)| s)
+            .sum::<f32>();
+        if wheels > self.config.min_speed * self.config.window.as_secs_f32()
+            && (p1 - p0).norm() < wheels * self.config.slip_ratio
+        {
+            Some(MotionFault::Slipping)
+        } else {
+            None
+        }
+    }
+}